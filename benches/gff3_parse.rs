@@ -0,0 +1,120 @@
+//! GFF3 parsing throughput: `cargo bench --bench gff3_parse [-- GENES]`.
+//!
+//! Parses a generated annotation with `parse_gff3` and with a parser that allocates per line
+//! as `thaf` did before (a `Vec` of columns, a `HashMap` of all attributes and owned strings
+//! for every region), and prints the throughput of both.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
+use thaf::gff3::{parse_gff3, parse_gff3_parallel};
+
+fn write_annotation(path: &std::path::Path, genes: usize) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    writeln!(out, "##gff-version 3")?;
+    for g in 0..genes {
+        let chromosome = format!("chr{}", g % 20 + 1);
+        let start = (g / 20) * 10_000 + 1;
+        writeln!(out, "{chromosome}\tbench\tgene\t{start}\t{}\t.\t+\t.\tID=gene{g};Name=G{g};biotype=protein_coding", start + 5000)?;
+        for t in 0..2 {
+            writeln!(out, "{chromosome}\tbench\tmRNA\t{start}\t{}\t.\t+\t.\tID=gene{g}.t{t};Parent=gene{g};Note=isoform {t}", start + 5000)?;
+            for e in 0..6 {
+                let s = start + e * 800;
+                let id = format!("gene{g}.t{t}");
+                writeln!(out, "{chromosome}\tbench\texon\t{s}\t{}\t.\t+\t.\tID={id}.exon{e};Parent={id};exon_number={e}", s + 300)?;
+                writeln!(out, "{chromosome}\tbench\tCDS\t{s}\t{}\t.\t+\t0\tID={id}.cds;Parent={id}", s + 300)?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Regions as the parser used to build them, with owned strings throughout.
+#[allow(dead_code)]
+struct OwnedRegion {
+    chromosome: String,
+    start: usize,
+    end: usize,
+    transcript_id: String,
+    region_id: String,
+    gene_id: Option<String>,
+}
+
+fn legacy_parse(path: &str) -> (Vec<OwnedRegion>, Vec<OwnedRegion>) {
+    let mut regions = Vec::new();
+    let mut cds = Vec::new();
+    let mut transcript_to_gene: HashMap<String, String> = HashMap::new();
+    for line in BufReader::new(File::open(path).unwrap()).lines() {
+        let line = line.unwrap();
+        if line.starts_with('#') {
+            continue;
+        }
+        let cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() != 9 {
+            continue;
+        }
+        let attributes: HashMap<String, String> = cols[8]
+            .split(';')
+            .filter_map(|a| a.split_once('='))
+            .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
+            .collect();
+        match cols[2] {
+            "mRNA" => {
+                transcript_to_gene.insert(attributes["ID"].clone(), attributes["Parent"].clone());
+            }
+            "exon" | "CDS" => {
+                let transcript_id = attributes["Parent"].clone();
+                let list = if cols[2] == "CDS" { &mut cds } else { &mut regions };
+                list.push(OwnedRegion {
+                    chromosome: cols[0].to_owned(),
+                    start: cols[3].parse().unwrap(),
+                    end: cols[4].parse().unwrap(),
+                    gene_id: transcript_to_gene.get(&transcript_id).cloned(),
+                    transcript_id,
+                    region_id: attributes["ID"].clone(),
+                })
+            }
+            _ => (),
+        }
+    }
+    (regions, cds)
+}
+
+fn report(name: &str, bytes: u64, elapsed: Duration, regions: usize) {
+    let mb = bytes as f64 / 1e6;
+    println!(
+        "{:<28} {:>8.1} ms {:>8.1} MB/s  ({} regions)",
+        name,
+        elapsed.as_secs_f64() * 1e3,
+        mb / elapsed.as_secs_f64(),
+        regions
+    );
+}
+
+fn main() {
+    let genes = std::env::args()
+        .skip(1)
+        .find_map(|a| a.parse::<usize>().ok())
+        .unwrap_or(50_000);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bench.gff3");
+    write_annotation(&path, genes).unwrap();
+    let path = path.to_str().unwrap();
+    let bytes = std::fs::metadata(path).unwrap().len();
+    println!("{} genes, {:.1} MB of GFF3", genes, bytes as f64 / 1e6);
+    let features = ["exon".to_string()];
+
+    let start = Instant::now();
+    let regions = legacy_parse(path).0.len();
+    report("per-line allocation", bytes, start.elapsed(), regions);
+
+    let start = Instant::now();
+    let regions = parse_gff3(path, &features, &mut Vec::new()).unwrap().regions.len();
+    report("parse_gff3", bytes, start.elapsed(), regions);
+
+    let threads = thaf::parallel::resolve_threads(0);
+    let start = Instant::now();
+    let regions = parse_gff3_parallel(path, &features, &mut Vec::new(), threads, false).unwrap().regions.len();
+    report(&format!("parse_gff3_parallel ({})", threads), bytes, start.elapsed(), regions);
+}
@@ -0,0 +1,314 @@
+use crate::error::{write_errors, Error, Severity};
+use crate::genome::open_genome_with;
+use crate::gff3::{parse_gff3_parallel, write_genemap};
+use crate::output::StagedOutputs;
+use crate::policy::Policy;
+use crate::transcript_builder::{build_transcripts, write_transcriptome};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// One genome/annotation pair of a batch manifest. Outputs are named from `prefix`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub gff3: String,
+    pub fasta: String,
+    pub prefix: String,
+}
+
+/// Outcome of one manifest entry.
+#[derive(Debug, Default)]
+pub struct EntrySummary {
+    pub transcripts: usize,
+    pub genes: usize,
+    pub warnings: usize,
+    pub fatal: usize,
+}
+
+/// Settings shared by the entries of a batch.
+pub struct EntryOptions<'a> {
+    pub features: &'a [String],
+    pub threads: usize,
+    pub keep_partial: bool,
+    pub rename_duplicates: bool,
+    pub policy: &'a Policy,
+}
+
+/// Read a batch manifest: TOML if the name ends with `.toml`, otherwise TSV with the columns
+/// name, gff3, fasta and prefix (an optional header line starting with `name` is skipped).
+/// Relative paths are taken from the directory of the manifest.
+pub fn read_manifest(path: &str) -> Result<Vec<ManifestEntry>> {
+    let text = std::fs::read_to_string(path)?;
+    let mut entries = if path.to_lowercase().ends_with(".toml") {
+        parse_toml_manifest(&text, path)?
+    } else {
+        parse_tsv_manifest(&text, path)?
+    };
+
+    let base = Path::new(path).parent().unwrap_or(Path::new(""));
+    let resolve = |p: &mut String| *p = base.join(&*p).to_string_lossy().into_owned();
+    let mut names = HashSet::new();
+    for entry in &mut entries {
+        if !names.insert(entry.name.clone()) {
+            anyhow::bail!("{}: entry name '{}' is used more than once", path, entry.name);
+        }
+        resolve(&mut entry.gff3);
+        resolve(&mut entry.fasta);
+        resolve(&mut entry.prefix);
+    }
+    Ok(entries)
+}
+
+fn parse_tsv_manifest(text: &str, path: &str) -> Result<Vec<ManifestEntry>> {
+    let mut entries = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        if line.starts_with('#') || line.trim().is_empty() || (line_no == 0 && line.starts_with("name\t")) {
+            continue;
+        }
+        let cols: Vec<&str> = line.split('\t').map(str::trim).collect();
+        let [name, gff3, fasta, prefix] = cols[..] else {
+            anyhow::bail!("{}:{}: expected 4 columns (name, gff3, fasta, prefix), found {}", path, line_no + 1, cols.len());
+        };
+        entries.push(ManifestEntry {
+            name: name.to_string(),
+            gff3: gff3.to_string(),
+            fasta: fasta.to_string(),
+            prefix: prefix.to_string(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Read the `[[entry]]` tables of a TOML manifest, whose keys are quoted strings.
+fn parse_toml_manifest(text: &str, path: &str) -> Result<Vec<ManifestEntry>> {
+    let mut tables: Vec<(usize, [Option<String>; 4])> = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('#') || line.is_empty() {
+            continue;
+        }
+        if line == "[[entry]]" {
+            tables.push((line_no + 1, Default::default()));
+            continue;
+        }
+        let parsed = line.split_once('=').and_then(|(key, value)| Some((key.trim(), toml_string(value.trim())?)));
+        let (Some((key, value)), Some((_, table))) = (parsed, tables.last_mut()) else {
+            anyhow::bail!("{}:{}: expected [[entry]] or key = \"value\"", path, line_no + 1);
+        };
+        let slot = match key {
+            "name" => &mut table[0],
+            "gff3" => &mut table[1],
+            "fasta" => &mut table[2],
+            "prefix" => &mut table[3],
+            _ => anyhow::bail!("{}:{}: unknown key '{}'", path, line_no + 1, key),
+        };
+        *slot = Some(value);
+    }
+
+    tables
+        .into_iter()
+        .map(|(line_no, [name, gff3, fasta, prefix])| {
+            let missing = |key| format!("{}:{}: entry without {}", path, line_no, key);
+            Ok(ManifestEntry {
+                name: name.with_context(|| missing("name"))?,
+                gff3: gff3.with_context(|| missing("gff3"))?,
+                fasta: fasta.with_context(|| missing("fasta"))?,
+                prefix: prefix.with_context(|| missing("prefix"))?,
+            })
+        })
+        .collect()
+}
+
+/// A TOML basic string (`"..."` with `\"` and `\\` escapes) or literal string (`'...'`).
+pub(crate) fn toml_string(value: &str) -> Option<String> {
+    if let Some(literal) = value.strip_prefix('\'') {
+        return literal.strip_suffix('\'').map(str::to_string);
+    }
+    let mut chars = value.strip_prefix('"')?.chars();
+    let mut out = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return chars.as_str().trim().is_empty().then_some(out),
+            '\\' => match chars.next()? {
+                c @ ('"' | '\\') => out.push(c),
+                't' => out.push('\t'),
+                _ => return None,
+            },
+            c => out.push(c),
+        }
+    }
+}
+
+/// Extract the transcriptome of one entry into `{prefix}.fa`, with `{prefix}.genemap.tsv`,
+/// and its problems, judged by the policy, in `{prefix}.log`. The outputs of an entry with
+/// fatal problems are removed unless `keep_partial`; the log is always written.
+pub fn run_entry(entry: &ManifestEntry, options: &EntryOptions) -> Result<EntrySummary> {
+    if let Some(dir) = Path::new(&entry.prefix).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut errors: Vec<Error> = Vec::new();
+    let mut outputs = StagedOutputs::new(options.keep_partial);
+    let result = extract_entry(entry, options, &mut outputs, &mut errors);
+    options.policy.judge(&mut errors);
+
+    let mut log = BufWriter::new(File::create(format!("{}.log", entry.prefix))?);
+    write_errors(&errors, &mut log)?;
+    if let Err(e) = &result {
+        writeln!(log, "[Failed] {:#}", e)?;
+    }
+    log.flush()?;
+
+    let (transcripts, genes) = result?;
+    let fatal = errors.iter().filter(|e| matches!(e.severity, Severity::Fatal)).count();
+    outputs.finish(fatal == 0)?;
+    Ok(EntrySummary { transcripts, genes, warnings: errors.len() - fatal, fatal })
+}
+
+fn extract_entry(
+    entry: &ManifestEntry,
+    options: &EntryOptions,
+    outputs: &mut StagedOutputs,
+    errors: &mut Vec<Error>,
+) -> Result<(usize, usize)> {
+    let EntryOptions { features, threads, rename_duplicates, policy, .. } = *options;
+    let annotation = parse_gff3_parallel(&entry.gff3, features, errors, threads, rename_duplicates)
+        .with_context(|| format!("Cannot read annotation {}", entry.gff3))?;
+    write_genemap(&annotation.regions, &outputs.stage(&format!("{}.genemap.tsv", entry.prefix)))?;
+    let transcripts = build_transcripts(annotation, errors);
+    let genes = transcripts.iter().map(|t| t.gene_id.as_ref().unwrap_or(&t.id)).collect::<HashSet<_>>().len();
+    if policy.too_many_warnings(errors) {
+        return Ok((transcripts.len(), genes));
+    }
+    let genome = open_genome_with(&entry.fasta, false, rename_duplicates, errors).with_context(|| format!("Cannot read genome {}", entry.fasta))?;
+    if policy.too_many_warnings(errors) {
+        return Ok((transcripts.len(), genes));
+    }
+    write_transcriptome(genome.as_ref(), &transcripts, &outputs.stage(&format!("{}.fa", entry.prefix)), threads)?;
+    Ok((transcripts.len(), genes))
+}
+
+/// Run every entry of a manifest with the same options, `jobs` entries at a time.
+/// An entry that fails, or panics, is recorded and does not stop the others. The summary
+/// TSV lists the entries in manifest order. Returns the number of entries that failed or
+/// reported fatal problems.
+pub fn run_batch(entries: &[ManifestEntry], options: &EntryOptions, jobs: usize, summary_path: &str) -> Result<usize> {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<EntrySummary>>>> = Mutex::new(entries.iter().map(|_| None).collect());
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, entries.len().max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(entry) = entries.get(i) else {
+                        break;
+                    };
+                    let result = std::panic::catch_unwind(|| run_entry(entry, options))
+                        .unwrap_or_else(|_| Err(anyhow::anyhow!("panicked")));
+                    println!("  {}: {}", entry.name, if result.is_ok() { "done" } else { "failed" });
+                    results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+                }
+            });
+        }
+    });
+
+    let mut summary = BufWriter::new(File::create(summary_path)?);
+    writeln!(summary, "name\tstatus\ttranscripts\tgenes\twarnings\tfatal\tlog\tmessage")?;
+    let mut failed = 0;
+    let results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    for (entry, result) in entries.iter().zip(results) {
+        let log = format!("{}.log", entry.prefix);
+        match result.expect("every entry is run") {
+            Ok(s) => {
+                let status = if s.fatal > 0 { "fatal" } else { "ok" };
+                failed += (s.fatal > 0) as usize;
+                writeln!(
+                    summary,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t",
+                    entry.name, status, s.transcripts, s.genes, s.warnings, s.fatal, log
+                )?;
+            }
+            Err(e) => {
+                failed += 1;
+                let message = format!("{:#}", e).replace(['\t', '\n'], " ");
+                writeln!(summary, "{}\tfailed\t\t\t\t\t{}\t{}", entry.name, log, message)?;
+            }
+        }
+    }
+    summary.flush()?;
+    Ok(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_manifest_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let tsv = dir.path().join("m.tsv");
+        std::fs::write(&tsv, "name\tgff3\tfasta\tprefix\nacc1\ta.gff3\t/data/a.fa\tout/acc1\n").unwrap();
+        let toml = dir.path().join("m.toml");
+        std::fs::write(
+            &toml,
+            "# accessions\n[[entry]]\nname = \"acc1\"\ngff3 = 'a.gff3'\nfasta = \"/data/a.fa\" \nprefix = \"out/acc1\"\n",
+        )
+        .unwrap();
+
+        let expected = vec![ManifestEntry {
+            name: "acc1".into(),
+            gff3: dir.path().join("a.gff3").to_string_lossy().into(),
+            fasta: "/data/a.fa".into(),
+            prefix: dir.path().join("out/acc1").to_string_lossy().into(),
+        }];
+        assert_eq!(read_manifest(tsv.to_str().unwrap()).unwrap(), expected);
+        assert_eq!(read_manifest(toml.to_str().unwrap()).unwrap(), expected);
+
+        std::fs::write(&toml, "[[entry]]\nname = \"acc1\"\n").unwrap();
+        let err = read_manifest(toml.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("entry without gff3"));
+    }
+
+    #[test]
+    fn test_failing_entry_does_not_stop_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        std::fs::write(path("a.gff3"), "chr1\t.\texon\t1\t4\t.\t+\t.\tID=e1;Parent=t1\n\
+            chr1\t.\texon\t6\t9\t.\t+\t.\tID=e2;Parent=t1\n").unwrap();
+        std::fs::write(path("a.fa"), ">chr1\nACGTACGTAC\n").unwrap();
+        let entries = vec![
+            ManifestEntry { name: "bad".into(), gff3: path("a.gff3"), fasta: path("missing.fa"), prefix: path("bad") },
+            ManifestEntry { name: "good".into(), gff3: path("a.gff3"), fasta: path("a.fa"), prefix: path("good") },
+        ];
+
+        let features = ["exon".to_string()];
+        let policy = Policy::default();
+        let options = EntryOptions { features: &features, threads: 1, keep_partial: false, rename_duplicates: false, policy: &policy };
+        let failed = run_batch(&entries, &options, 2, &path("summary.tsv")).unwrap();
+        assert_eq!(failed, 1);
+        assert_eq!(std::fs::read_to_string(path("good.fa")).unwrap(), ">t1\nACGTCGTA\n");
+        let summary = std::fs::read_to_string(path("summary.tsv")).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert!(lines[1].starts_with("bad\tfailed\t"));
+        assert!(lines[2].starts_with("good\tok\t1\t1\t0\t0\t"));
+        assert!(std::fs::read_to_string(path("bad.log")).unwrap().contains("[Failed]"));
+        // The genemap of the failed entry was written before the genome failed to open
+        assert!(!Path::new(&path("bad.genemap.tsv")).exists());
+
+        // The warning limit applies to each entry: a 3-base exon is one warning too many
+        std::fs::write(path("short.gff3"), "chr1\t.\texon\t1\t3\t.\t+\t.\tID=e1;Parent=t1\n\
+            chr1\t.\texon\t6\t9\t.\t+\t.\tID=e2;Parent=t1\n").unwrap();
+        let entry = ManifestEntry { name: "short".into(), gff3: path("short.gff3"), fasta: path("a.fa"), prefix: path("short") };
+        let mut policy = Policy::default();
+        policy.set_max_warnings(0);
+        let summary = run_entry(&entry, &EntryOptions { policy: &policy, ..options }).unwrap();
+        assert_eq!((summary.warnings, summary.fatal), (1, 1));
+        assert!(!Path::new(&path("short.fa")).exists());
+        assert!(std::fs::read_to_string(path("short.log")).unwrap().contains("TOO_MANY_WARNINGS"));
+    }
+}
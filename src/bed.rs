@@ -0,0 +1,68 @@
+use crate::structures::Transcript;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Write transcripts as BED12, one line per transcript, in the order given.
+/// Exons become blocks; thickStart/thickEnd come from the CDS span when known.
+pub fn write_bed12(transcripts: &[Transcript], out_path: &str) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(out_path)?);
+    for transcript in transcripts {
+        writeln!(writer, "{}", bed12_line(transcript))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Format a single transcript as a BED12 line (0-based, half-open coordinates).
+pub fn bed12_line(transcript: &Transcript) -> String {
+    let chrom_start = transcript.start() - 1;
+    let chrom_end = transcript.end();
+
+    // Non-coding transcripts get an empty thick region at chromStart, as UCSC tools expect.
+    let (thick_start, thick_end) = match transcript.cds {
+        Some((start, end)) => (start - 1, end),
+        None => (chrom_start, chrom_start),
+    };
+
+    let blocks = transcript.ascending_regions();
+    let sizes: Vec<String> = blocks
+        .iter()
+        .map(|r| (r.end - r.start + 1).to_string())
+        .collect();
+    let starts: Vec<String> = blocks
+        .iter()
+        .map(|r| (r.start - 1 - chrom_start).to_string())
+        .collect();
+
+    format!(
+        "{}\t{}\t{}\t{}\t0\t{}\t{}\t{}\t0\t{}\t{},\t{},",
+        transcript.chromosome,
+        chrom_start,
+        chrom_end,
+        transcript.id,
+        transcript.strand(),
+        thick_start,
+        thick_end,
+        blocks.len(),
+        sizes.join(","),
+        starts.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::Strand;
+
+    #[test]
+    fn test_bed12_coding_plus() {
+        let line = bed12_line(&Transcript::test("tx1", "chr1", Strand::Plus, &[(101, 150), (201, 260)]).coding(Some((121, 230))));
+        assert_eq!(line, "chr1\t100\t260\ttx1\t0\t+\t120\t230\t0\t2\t50,60,\t0,100,");
+    }
+
+    #[test]
+    fn test_bed12_noncoding_minus_blocks_ascending() {
+        let line = bed12_line(&Transcript::test("tx1", "chr1", Strand::Minus, &[(201, 260), (101, 150)]).region_ids(&["e2", "e1"]));
+        assert_eq!(line, "chr1\t100\t260\ttx1\t0\t-\t100\t100\t0\t2\t50,60,\t0,100,");
+    }
+}
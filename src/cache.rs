@@ -0,0 +1,315 @@
+use crate::error::Error;
+use crate::gff3::{read_records, Attributes, Linker, Record};
+use crate::output::StagedOutputs;
+use crate::structures::Annotation;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::ops::Range;
+
+const MAGIC: &[u8; 8] = b"THAFGFF\0";
+
+/// Bumped whenever the layout of the cache changes; caches of other versions are rebuilt.
+pub const CACHE_VERSION: u32 = 6;
+
+/// Marks an absent attribute in the string references of a cached feature.
+const NONE: u32 = u32::MAX;
+
+/// 64-bit checksum of a file, identifying the GFF3 a cache was built from. FNV-1a taken over
+/// 8-byte words rather than bytes, with a rotation to spread the high bits, so that hashing
+/// keeps up with reading. The last word is padded with zeros.
+pub fn checksum(path: &str) -> Result<u64> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0u8; 1 << 20];
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    loop {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match file.read(&mut buffer[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        let padded = filled.next_multiple_of(8);
+        buffer[filled..padded].fill(0);
+        for word in buffer[..padded].chunks_exact(8) {
+            let word = u64::from_le_bytes(word.try_into().unwrap());
+            hash = (hash ^ word).wrapping_mul(0x0100_0000_01b3).rotate_left(29);
+        }
+        if filled < buffer.len() {
+            return Ok(hash);
+        }
+    }
+}
+
+/// Whether `parse_gff3_cached` read the annotation from the cache or rebuilt it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheStatus {
+    Loaded,
+    Rebuilt,
+}
+
+/// Parse a GFF3 through a binary cache of its feature lines. The cache holds the features of
+/// every type, so it serves any feature set. It is used when its version matches and the
+/// GFF3 has the size and checksum it was built from; a GFF3 of another size is not hashed.
+/// It is rebuilt from the GFF3 otherwise, or if it cannot be read. The annotation and the reported
+/// problems are the same as those of `parse_gff3`.
+pub fn parse_gff3_cached(
+    gff3_path: &str,
+    cache_path: &str,
+    feature_types: &[String],
+    errors: &mut Vec<Error>,
+    threads: usize,
+    rename_duplicates: bool,
+) -> Result<(Annotation, CacheStatus)> {
+    let size = std::fs::metadata(gff3_path)?.len();
+    let mut sum = None;
+    if let Some(cache) = read_cache(cache_path, gff3_path, size, &mut sum)? {
+        // Problems are only kept if the whole cache can be replayed
+        let mut cached_errors = Vec::new();
+        if let Ok(annotation) = cache.load(gff3_path, feature_types, rename_duplicates, &mut cached_errors) {
+            errors.extend(cached_errors);
+            return Ok((annotation, CacheStatus::Loaded));
+        }
+    }
+
+    let mut cache = CacheWriter::default();
+    let mut linker = Linker::new(gff3_path, feature_types, rename_duplicates);
+    read_records(gff3_path, None, threads, |record| {
+        cache.add(&record);
+        linker.add(record, errors);
+        Ok(())
+    })?;
+    let sum = match sum {
+        Some(sum) => sum,
+        None => checksum(gff3_path)?,
+    };
+    cache.write(cache_path, sum, size)?;
+    Ok((linker.finish(errors), CacheStatus::Rebuilt))
+}
+
+/// Encodes features as fixed-size records referring to a table of distinct strings.
+#[derive(Default)]
+struct CacheWriter {
+    strings: Vec<String>,
+    index: HashMap<String, u32>,
+    records: Vec<u8>,
+    count: u64,
+}
+
+impl CacheWriter {
+    fn string(&mut self, s: Option<&str>) -> u32 {
+        let Some(s) = s else {
+            return NONE;
+        };
+        if let Some(&i) = self.index.get(s) {
+            return i;
+        }
+        let i = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.index.insert(s.to_string(), i);
+        i
+    }
+
+    fn add(&mut self, record: &Record) {
+        let refs = [
+            self.string(Some(record.chromosome)),
+            self.string(Some(record.feature_type)),
+            self.string(record.attributes.id),
+            self.string(record.attributes.parent),
+            self.string(record.attributes.name),
+            self.string(record.attributes.biotype),
+        ];
+        for r in refs {
+            self.records.extend_from_slice(&r.to_le_bytes());
+        }
+        self.records.extend_from_slice(&(record.start as u64).to_le_bytes());
+        self.records.extend_from_slice(&(record.end as u64).to_le_bytes());
+        self.records.extend_from_slice(&(record.strand_char as u32).to_le_bytes());
+        self.records.extend_from_slice(&record.phase.map_or(NONE, u32::from).to_le_bytes());
+        self.records.extend_from_slice(&(record.line as u64).to_le_bytes());
+        self.count += 1;
+    }
+
+    /// Write the cache under a temporary name and move it into place, so that a run that
+    /// stops while writing leaves no truncated cache behind.
+    fn write(&self, path: &str, checksum: u64, size: u64) -> Result<()> {
+        let mut staged = StagedOutputs::new(false);
+        let temporary = staged.stage(path);
+        let mut out = BufWriter::new(File::create(&temporary)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&CACHE_VERSION.to_le_bytes())?;
+        out.write_all(&checksum.to_le_bytes())?;
+        out.write_all(&size.to_le_bytes())?;
+        out.write_all(&(self.strings.len() as u64).to_le_bytes())?;
+        for s in &self.strings {
+            out.write_all(&(s.len() as u32).to_le_bytes())?;
+            out.write_all(s.as_bytes())?;
+        }
+        out.write_all(&self.count.to_le_bytes())?;
+        out.write_all(&self.records)?;
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        staged.finish(true)
+    }
+}
+
+/// Size of an encoded feature: six string references, start, end, strand, phase and line.
+const RECORD_SIZE: usize = 6 * 4 + 8 + 8 + 4 + 4 + 8;
+
+struct Cache {
+    strings: Vec<String>,
+    data: Vec<u8>,
+    records: Range<usize>,
+}
+
+/// Read a cache if it exists, has the current version and was built from this GFF3, as
+/// told by its size and checksum. The checksum, if computed, is left in `sum`. A cache that
+/// cannot be decoded is treated as missing.
+fn read_cache(path: &str, gff3_path: &str, size: u64, sum: &mut Option<u64>) -> Result<Option<Cache>> {
+    let Ok(data) = std::fs::read(path) else {
+        return Ok(None);
+    };
+    let mut cursor = Cursor { data: &data, pos: 0 };
+    if cursor.take(MAGIC.len()) != Some(MAGIC) || cursor.u32() != Some(CACHE_VERSION) {
+        return Ok(None);
+    }
+    let (Some(cached_sum), Some(cached_size)) = (cursor.u64(), cursor.u64()) else {
+        return Ok(None);
+    };
+    if cached_size != size || cached_sum != *sum.insert(checksum(gff3_path)?) {
+        return Ok(None);
+    }
+    Ok(decode(&data, cursor.pos).map(|(strings, records)| Cache { strings, data, records }))
+}
+
+/// The string table and the range of the feature records of a cache, read from `pos`.
+fn decode(data: &[u8], pos: usize) -> Option<(Vec<String>, Range<usize>)> {
+    let mut cursor = Cursor { data, pos };
+    let count = cursor.u64()? as usize;
+    let mut strings = Vec::with_capacity(count.min(data.len()));
+    for _ in 0..count {
+        let len = cursor.u32()? as usize;
+        strings.push(String::from_utf8(cursor.take(len)?.to_vec()).ok()?);
+    }
+    let count = cursor.u64()? as usize;
+    let start = cursor.pos;
+    cursor.take(count.checked_mul(RECORD_SIZE)?)?;
+    (cursor.pos == data.len()).then_some((strings, start..cursor.pos))
+}
+
+impl Cache {
+    /// Replay the cached features through the linker, as if read from the GFF3. The linked
+    /// annotation is not stored: which features become regions, the renamed IDs and the
+    /// problems reported all depend on `-e` and `--rename-duplicates`, and one cache serves
+    /// every run. Loading skips reading, splitting and decoding the GFF3 text; linking and
+    /// validation run again on the decoded features.
+    fn load(&self, gff3_path: &str, feature_types: &[String], rename_duplicates: bool, errors: &mut Vec<Error>) -> Result<Annotation> {
+        let mut linker = Linker::new(gff3_path, feature_types, rename_duplicates);
+        for raw in self.data[self.records.clone()].chunks_exact(RECORD_SIZE) {
+            let mut cursor = Cursor { data: raw, pos: 0 };
+            let mut string = || -> Result<Option<&str>> {
+                match cursor.u32() {
+                    Some(NONE) => Ok(None),
+                    Some(i) => Ok(Some(self.strings.get(i as usize).context("string out of range")?)),
+                    None => anyhow::bail!("truncated record"),
+                }
+            };
+            let chromosome = string()?.context("missing seqid")?;
+            let feature_type = string()?.context("missing type")?;
+            let attributes = Attributes { id: string()?, parent: string()?, name: string()?, biotype: string()? };
+            let start = cursor.u64().context("truncated record")? as usize;
+            let end = cursor.u64().context("truncated record")? as usize;
+            let strand_char = cursor.u32().and_then(char::from_u32).context("bad strand")?;
+            let phase = match cursor.u32().context("truncated record")? {
+                NONE => None,
+                p => Some(u8::try_from(p).context("bad phase")?),
+            };
+            let line = cursor.u64().context("truncated record")? as usize;
+            linker.add(Record { chromosome, feature_type, start, end, strand_char, phase, attributes, line }, errors);
+        }
+        Ok(linker.finish(errors))
+    }
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gff3::parse_gff3;
+
+    #[test]
+    fn test_cache_matches_gff3_and_is_rebuilt() {
+        let dir = tempfile::tempdir().unwrap();
+        let gff3 = dir.path().join("a.gff3");
+        let cache = dir.path().join("a.cache");
+        let (gff3, cache) = (gff3.to_str().unwrap(), cache.to_str().unwrap());
+        std::fs::write(gff3, "chr1\t.\tgene\t1\t50\t.\t-\t.\tID=g1;Name=Abc;biotype=protein_coding\n\
+            chr1\t.\tmRNA\t1\t50\t.\t-\t.\tID=t1;Parent=g1\n\
+            chr1\t.\texon\t1\t10\t.\t-\t.\tID=e1;Parent=t1\n\
+            chr1\t.\tCDS\t5\t10\t.\t-\t0\tParent=t1\n\
+            chr1\t.\texon\t20\t50\t.\t-\t.\tParent=t1\n").unwrap();
+
+        for features in [vec!["exon".to_string()], vec!["exon".to_string(), "CDS".to_string()]] {
+            let mut expected_errors = Vec::new();
+            let expected = parse_gff3(gff3, &features, &mut expected_errors).unwrap();
+            std::fs::remove_file(cache).ok();
+            for status in [CacheStatus::Rebuilt, CacheStatus::Loaded] {
+                let mut errors = Vec::new();
+                let (annotation, loaded) = parse_gff3_cached(gff3, cache, &features, &mut errors, 1, false).unwrap();
+                assert_eq!(loaded, status);
+                assert_eq!(format!("{:?}", annotation.regions), format!("{:?}", expected.regions));
+                assert_eq!(format!("{:?}", annotation.cds), format!("{:?}", expected.cds));
+                assert_eq!(annotation.gene_names, expected.gene_names);
+                assert_eq!(annotation.biotypes, expected.biotypes);
+                assert_eq!(format!("{:?}", errors), format!("{:?}", expected_errors));
+            }
+        }
+
+        // A changed GFF3 invalidates the cache
+        std::fs::write(gff3, "chr2\t.\texon\t1\t10\t.\t+\t.\tID=e9;Parent=t9\n").unwrap();
+        let (annotation, status) = parse_gff3_cached(gff3, cache, &["exon".to_string()], &mut Vec::new(), 1, false).unwrap();
+        assert_eq!(status, CacheStatus::Rebuilt);
+        assert_eq!(&*annotation.regions[0].chromosome, "chr2");
+
+        // So does a change that keeps the size and modification time
+        let modified = std::fs::metadata(gff3).unwrap().modified().unwrap();
+        std::fs::write(gff3, "chr3\t.\texon\t1\t10\t.\t+\t.\tID=e9;Parent=t9\n").unwrap();
+        File::options().write(true).open(gff3).unwrap().set_modified(modified).unwrap();
+        let (annotation, status) = parse_gff3_cached(gff3, cache, &["exon".to_string()], &mut Vec::new(), 1, false).unwrap();
+        assert_eq!(status, CacheStatus::Rebuilt);
+        assert_eq!(&*annotation.regions[0].chromosome, "chr3");
+
+        // A truncated or damaged cache is rebuilt, and no temporary file is left behind
+        let data = std::fs::read(cache).unwrap();
+        for damaged in [data[..data.len() - 3].to_vec(), [&data[..40], &[0xff; 64][..]].concat()] {
+            std::fs::write(cache, damaged).unwrap();
+            let mut errors = Vec::new();
+            let (annotation, status) = parse_gff3_cached(gff3, cache, &["exon".to_string()], &mut errors, 1, false).unwrap();
+            assert_eq!(status, CacheStatus::Rebuilt);
+            assert_eq!(&*annotation.regions[0].chromosome, "chr3");
+            assert_eq!(std::fs::read(cache).unwrap(), data);
+        }
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+}
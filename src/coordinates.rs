@@ -0,0 +1,385 @@
+use crate::error::{Code, Error};
+use crate::genome::GenomeSource;
+use crate::structures::{Strand, Transcript};
+use bio::alphabets::dna;
+use bio::data_structures::interval_tree::IntervalTree;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// Direction of a coordinate conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToTranscript,
+    ToGenome,
+}
+
+impl Transcript {
+    /// Transcript position (1-based) of a genomic position, or None if no region covers it.
+    pub fn to_transcript_position(&self, pos: usize) -> Option<usize> {
+        self.transcript_offset(pos).map(|offset| offset + 1)
+    }
+
+    /// Genomic position of a transcript position (1-based), or None if outside the transcript.
+    pub fn to_genome_position(&self, pos: usize) -> Option<usize> {
+        if pos == 0 {
+            return None;
+        }
+        self.genomic_segments(pos - 1, pos).first().map(|&(_, start, _)| start)
+    }
+
+    /// Transcript intervals (1-based, inclusive) covered by the genomic interval `start..=end`,
+    /// one per region it overlaps, in transcript order. Intronic parts are dropped.
+    pub fn to_transcript_intervals(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        let mut intervals = Vec::new();
+        let mut offset = 0;
+        for region in &self.regions {
+            let (lo, hi) = (start.max(region.start), end.min(region.end));
+            if lo <= hi {
+                intervals.push(match region.strand {
+                    Strand::Plus => (offset + lo - region.start + 1, offset + hi - region.start + 1),
+                    Strand::Minus => (offset + region.end - hi + 1, offset + region.end - lo + 1),
+                });
+            }
+            offset += region.end - region.start + 1;
+        }
+        intervals
+    }
+
+    /// Genomic intervals (1-based, inclusive) covered by the transcript interval `start..=end`,
+    /// split at exon junctions, in transcript order.
+    pub fn to_genome_intervals(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        if start == 0 || start > end {
+            return Vec::new();
+        }
+        self.genomic_segments(start - 1, end)
+            .into_iter()
+            .map(|(_, start, end)| (start, end))
+            .collect()
+    }
+}
+
+/// Looks up transcripts by ID, or by overlap with a genomic interval.
+pub struct TranscriptIndex<'a> {
+    by_id: HashMap<&'a str, &'a Transcript>,
+    by_chromosome: HashMap<&'a str, IntervalTree<usize, &'a Transcript>>,
+}
+
+impl<'a> TranscriptIndex<'a> {
+    pub fn new(transcripts: &'a [Transcript]) -> Self {
+        let mut by_id = HashMap::new();
+        let mut by_chromosome: HashMap<&str, IntervalTree<usize, &Transcript>> = HashMap::new();
+        for t in transcripts {
+            by_id.insert(t.id.as_str(), t);
+            by_chromosome
+                .entry(t.chromosome.as_str())
+                .or_default()
+                .insert(t.start()..t.end() + 1, t);
+        }
+        Self { by_id, by_chromosome }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&'a Transcript> {
+        self.by_id.get(id).copied()
+    }
+
+    /// Transcripts whose extent overlaps the genomic interval `start..=end`, sorted by ID.
+    /// An inverted interval overlaps nothing.
+    pub fn overlapping(&self, chromosome: &str, start: usize, end: usize) -> Vec<&'a Transcript> {
+        let Some(tree) = self.by_chromosome.get(chromosome) else {
+            return Vec::new();
+        };
+        if start > end {
+            return Vec::new();
+        }
+        let mut found: Vec<&Transcript> = tree.find(start..end + 1).map(|e| *e.data()).collect();
+        found.sort_by(|a, b| a.id.cmp(&b.id));
+        found
+    }
+}
+
+/// Convert a BED file between genomic and transcript coordinates. Towards transcripts, a record
+/// is written once per overlapping transcript and exon; towards the genome, it is split at exon
+/// junctions. Only the first six columns are kept, with the strand flipped on minus-strand transcripts.
+pub fn map_bed(
+    input_path: &str,
+    output_path: &str,
+    transcripts: &[Transcript],
+    direction: Direction,
+    errors: &mut Vec<Error>,
+) -> anyhow::Result<()> {
+    let index = TranscriptIndex::new(transcripts);
+    let reader = BufReader::new(File::open(input_path)?);
+    let mut writer = BufWriter::new(File::create(output_path)?);
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") || line.trim().is_empty() {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let cols: Vec<&str> = line.split('\t').collect();
+        let (Some(Ok(start)), Some(Ok(end))) = (
+            cols.get(1).map(|c| c.parse::<usize>()),
+            cols.get(2).map(|c| c.parse::<usize>()),
+        ) else {
+            errors.push(Error::warning(Code::InvalidRecord, format!("{}:{} is not a BED record", input_path, line_no + 1)).at(input_path, line_no + 1));
+            continue;
+        };
+        if start > end {
+            errors.push(Error::warning(Code::InvalidRecord, format!("{}:{} starts after its end", input_path, line_no + 1)).at(input_path, line_no + 1));
+            continue;
+        }
+        let extra = &cols[3..cols.len().min(6)];
+
+        let targets = match direction {
+            Direction::ToTranscript => index.overlapping(cols[0], start + 1, end),
+            Direction::ToGenome => match index.get(cols[0]) {
+                Some(t) => vec![t],
+                None => {
+                    errors.push(Error::warning(Code::UnknownTranscript, format!(
+                        "{}:{} refers to unknown transcript {}",
+                        input_path,
+                        line_no + 1,
+                        cols[0]
+                    )).for_transcript(cols[0]).at(input_path, line_no + 1));
+                    continue;
+                }
+            },
+        };
+
+        for transcript in targets {
+            let (name, intervals) = match direction {
+                Direction::ToTranscript => (&transcript.id, transcript.to_transcript_intervals(start + 1, end)),
+                Direction::ToGenome => (&transcript.chromosome, transcript.to_genome_intervals(start + 1, end)),
+            };
+            for (a, b) in intervals {
+                write!(writer, "{}\t{}\t{}", name, a - 1, b)?;
+                for (i, col) in extra.iter().enumerate() {
+                    let col = match (i, transcript.strand(), *col) {
+                        (2, Strand::Minus, "+") => "-",
+                        (2, Strand::Minus, "-") => "+",
+                        (_, _, col) => col,
+                    };
+                    write!(writer, "\t{}", col)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Convert a VCF file between genomic and transcript coordinates. On minus-strand transcripts
+/// alleles are reverse-complemented and indels re-anchored, which needs the genome sequence.
+/// Variants that cross an exon boundary are reported and skipped. Contig headers are replaced
+/// by the transcripts when mapping towards transcripts, and dropped otherwise.
+pub fn map_vcf(
+    input_path: &str,
+    output_path: &str,
+    transcripts: &[Transcript],
+    genome: Option<&dyn GenomeSource>,
+    direction: Direction,
+    errors: &mut Vec<Error>,
+) -> anyhow::Result<()> {
+    let index = TranscriptIndex::new(transcripts);
+    let reader = BufReader::new(File::open(input_path)?);
+    let mut writer = BufWriter::new(File::create(output_path)?);
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with("##contig") {
+            continue;
+        } else if line.starts_with("#CHROM") {
+            if direction == Direction::ToTranscript {
+                for t in transcripts {
+                    writeln!(writer, "##contig=<ID={},length={}>", t.id, t.size())?;
+                }
+            }
+            writeln!(writer, "{}", line)?;
+            continue;
+        } else if line.starts_with('#') || line.trim().is_empty() {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+
+        let cols: Vec<&str> = line.split('\t').collect();
+        let Some(Ok(pos)) = cols.get(1).map(|c| c.parse::<usize>()) else {
+            errors.push(Error::warning(Code::InvalidRecord, format!("{}:{} is not a VCF record", input_path, line_no + 1)).at(input_path, line_no + 1));
+            continue;
+        };
+        let (Some(reference), Some(alts)) = (cols.get(3), cols.get(4)) else {
+            errors.push(Error::warning(Code::InvalidRecord, format!("{}:{} is not a VCF record", input_path, line_no + 1)).at(input_path, line_no + 1));
+            continue;
+        };
+        let alts: Vec<&str> = alts.split(',').collect();
+
+        let targets = match direction {
+            Direction::ToTranscript => index.overlapping(cols[0], pos, pos + reference.len().max(1) - 1),
+            Direction::ToGenome => index.get(cols[0]).into_iter().collect(),
+        };
+
+        for transcript in targets {
+            match map_variant(transcript, direction, pos, reference, &alts, genome) {
+                Ok((pos, reference, alts)) => {
+                    let name = match direction {
+                        Direction::ToTranscript => &transcript.id,
+                        Direction::ToGenome => &transcript.chromosome,
+                    };
+                    write!(writer, "{}\t{}\t{}\t{}\t{}", name, pos, cols[2], reference, alts.join(","))?;
+                    for col in &cols[5..] {
+                        write!(writer, "\t{}", col)?;
+                    }
+                    writeln!(writer)?;
+                }
+                Err(reason) => errors.push(
+                    Error::warning(Code::UnmappedVariant, format!(
+                        "Variant at {}:{} not mapped to transcript {}: {}",
+                        cols[0], pos, transcript.id, reason
+                    ))
+                    .for_transcript(&transcript.id)
+                    .at(input_path, line_no + 1),
+                ),
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Map one VCF variant to the other coordinate system, returning position, REF and ALT alleles.
+fn map_variant(
+    transcript: &Transcript,
+    direction: Direction,
+    pos: usize,
+    reference: &str,
+    alts: &[&str],
+    genome: Option<&dyn GenomeSource>,
+) -> Result<(usize, String, Vec<String>), String> {
+    let map = |p: usize| match direction {
+        Direction::ToTranscript => transcript.to_transcript_position(p),
+        Direction::ToGenome => transcript.to_genome_position(p),
+    };
+    // The source span must map onto consecutive positions, in either orientation
+    let contiguous = |length: usize| {
+        let Some(first) = map(pos) else { return false };
+        (0..length).all(|k| map(pos + k).is_some_and(|p| p.abs_diff(first) == k))
+    };
+    let length = reference.len();
+
+    if transcript.strand() == Strand::Plus {
+        if !contiguous(length) {
+            return Err("variant crosses an exon boundary".to_string());
+        }
+        return Ok((map(pos).unwrap(), reference.to_string(), alts.iter().map(|a| a.to_string()).collect()));
+    }
+
+    let is_sequence = |allele: &str| !allele.is_empty() && allele.bytes().all(|b| b"ACGTNacgtn".contains(&b));
+    if !is_sequence(reference) || !alts.iter().all(|a| is_sequence(a)) {
+        return Err("symbolic alleles cannot be reverse-complemented".to_string());
+    }
+    let revcomp = |allele: &[u8]| String::from_utf8(dna::revcomp(allele)).unwrap();
+
+    if alts.iter().all(|a| a.len() == length) {
+        if !contiguous(length) {
+            return Err("variant crosses an exon boundary".to_string());
+        }
+        let alleles = alts.iter().map(|a| revcomp(a.as_bytes())).collect();
+        return Ok((map(pos + length - 1).unwrap(), revcomp(reference.as_bytes()), alleles));
+    }
+
+    // Indel: the anchor base moves from before the event to the base after it
+    let anchor = reference.as_bytes()[0].to_ascii_uppercase();
+    if alts.iter().any(|a| a.as_bytes()[0].to_ascii_uppercase() != anchor) {
+        return Err("alleles do not share an anchor base".to_string());
+    }
+    if !contiguous(length + 1) {
+        return Err("variant crosses an exon boundary".to_string());
+    }
+    let genome = genome.ok_or("indels on the minus strand need the genome sequence")?;
+    let next = map(pos + length).unwrap();
+    let genomic_next = match direction {
+        Direction::ToTranscript => pos + length,
+        Direction::ToGenome => next,
+    };
+    let mut base = Vec::with_capacity(1);
+    genome
+        .fetch(&transcript.chromosome, genomic_next, genomic_next, &mut base)
+        .map_err(|e| e.to_string())?;
+    let next_base = match direction {
+        Direction::ToTranscript => base[0],
+        Direction::ToGenome => dna::complement(base[0]),
+    };
+    let new_anchor = dna::complement(next_base) as char;
+    let reanchor = |allele: &str| format!("{}{}", new_anchor, revcomp(&allele.as_bytes()[1..]));
+    Ok((next, reanchor(reference), alts.iter().map(|a| reanchor(a)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minus_transcript() -> Transcript {
+        // Genome positions 11..20 and 31..40, transcript runs 40 -> 31, 20 -> 11
+        Transcript::test("tx1", "chr1", Strand::Minus, &[(31, 40), (11, 20)])
+    }
+
+    #[test]
+    fn test_position_round_trip() {
+        let t = minus_transcript();
+        assert_eq!(t.to_transcript_position(40), Some(1));
+        assert_eq!(t.to_transcript_position(20), Some(11));
+        assert_eq!(t.to_transcript_position(25), None);
+        for pos in 1..=20 {
+            assert_eq!(t.to_transcript_position(t.to_genome_position(pos).unwrap()), Some(pos));
+        }
+        assert_eq!(t.to_genome_position(21), None);
+    }
+
+    #[test]
+    fn test_intervals_split_at_junction() {
+        let t = minus_transcript();
+        assert_eq!(t.to_genome_intervals(8, 13), vec![(31, 33), (18, 20)]);
+        assert_eq!(t.to_transcript_intervals(18, 33), vec![(8, 10), (11, 13)]);
+    }
+
+    #[test]
+    fn test_map_variant_minus_strand() {
+        let t = minus_transcript();
+        let genome = HashMap::from([("chr1".to_string(), b"AAAAAAAAAACCCCCGGGGGTTTTTTTTTTACGTACGTAC".to_vec())]);
+        // SNV at 35 (A) is transcript position 6 (T)
+        let snv = map_variant(&t, Direction::ToTranscript, 35, "A", &["C"], Some(&genome)).unwrap();
+        assert_eq!(snv, (6, "T".to_string(), vec!["G".to_string()]));
+        // Deleting AC at 35..36 after anchor T at 34; on the transcript this is GT after C at position 4
+        let del = map_variant(&t, Direction::ToTranscript, 34, "TAC", &["T"], Some(&genome)).unwrap();
+        assert_eq!(del, (4, "CGT".to_string(), vec!["C".to_string()]));
+        let back = map_variant(&t, Direction::ToGenome, 4, "CGT", &["C"], Some(&genome)).unwrap();
+        assert_eq!(back, (34, "TAC".to_string(), vec!["T".to_string()]));
+        assert!(map_variant(&t, Direction::ToTranscript, 34, "TAC", &["T"], None).is_err());
+    }
+
+    #[test]
+    fn test_map_bed_skips_inverted_records() {
+        let transcripts = [minus_transcript()];
+        assert!(TranscriptIndex::new(&transcripts).overlapping("chr1", 20, 10).is_empty());
+
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.bed");
+        let output = dir.path().join("out.bed");
+        let (input, output) = (input.to_str().unwrap(), output.to_str().unwrap());
+        for (direction, lines) in [
+            (Direction::ToTranscript, "chr1\t20\t10\tx\nchr1\t32\t35\ty\n"),
+            (Direction::ToGenome, "tx1\t20\t10\tx\ntx1\t5\t8\ty\n"),
+        ] {
+            std::fs::write(input, lines).unwrap();
+            let mut errors = Vec::new();
+            map_bed(input, output, &transcripts, direction, &mut errors).unwrap();
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].code, Code::InvalidRecord);
+            assert_eq!(std::fs::read_to_string(output).unwrap().lines().count(), 1);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::error::{Code, Error};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The ID given to the `n`-th occurrence (from 2) of a repeated ID when renaming.
+pub fn suffixed(id: &str, n: usize) -> String {
+    format!("{}_dup{}", id, n)
+}
+
+/// Names of the records of a genome FASTA, in file order. A repeated name is reported as
+/// fatal and its record skipped, or, with `rename`, given a suffix and reported as a warning.
+pub struct SequenceNames {
+    path: String,
+    rename: bool,
+    counts: HashMap<String, usize>,
+    problems: Vec<Error>,
+}
+
+impl SequenceNames {
+    pub fn new(path: &str, rename: bool) -> Self {
+        SequenceNames { path: path.to_string(), rename, counts: HashMap::new(), problems: Vec::new() }
+    }
+
+    /// The name to store the record `id` under, or None to skip it.
+    pub fn name(&mut self, id: &str) -> Option<String> {
+        let count = self.counts.entry(id.to_string()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            return Some(id.to_string());
+        }
+        if self.rename {
+            let name = suffixed(id, *count);
+            self.problems.push(Error::warning(
+                Code::DuplicateSequenceId,
+                format!("Sequence {} appears {} times in {}; this copy is renamed {}", id, count, self.path, name),
+            ));
+            Some(name)
+        } else {
+            self.problems.push(Error::fatal(
+                Code::DuplicateSequenceId,
+                format!(
+                    "Sequence {} appears {} times in {}; only the first is used (see --rename-duplicates)",
+                    id, count, self.path
+                ),
+            ));
+            None
+        }
+    }
+
+    /// Move the reported problems to `errors`.
+    pub fn finish(self, errors: &mut Vec<Error>) {
+        errors.extend(self.problems);
+    }
+}
+
+/// First use of an ID in a GFF3.
+struct FirstUse {
+    feature_type: Arc<str>,
+    parent: Option<Arc<str>>,
+    line: usize,
+    count: usize,
+}
+
+/// IDs of the GFF3 lines seen so far. A line reusing an ID is a duplicate unless it has the
+/// same type and parent as the first line with it, which is a multi-line feature. Duplicates
+/// are reported as fatal, or, with `rename`, given a suffix and reported as warnings; later
+/// lines naming the ID as their `Parent` then refer to the renamed feature.
+#[derive(Default)]
+pub(crate) struct FeatureIds {
+    rename: bool,
+    first: HashMap<Arc<str>, FirstUse>,
+    renamed: HashMap<Arc<str>, Arc<str>>, // original ID to its latest name
+}
+
+impl FeatureIds {
+    pub(crate) fn new(rename: bool) -> Self {
+        FeatureIds { rename, ..Default::default() }
+    }
+
+    /// The ID that a `Parent` attribute refers to.
+    pub(crate) fn parent(&self, parent: Arc<str>) -> Arc<str> {
+        self.renamed.get(&parent).cloned().unwrap_or(parent)
+    }
+
+    /// Whether a line with ID `id` has been seen.
+    pub(crate) fn contains(&self, id: &str) -> bool {
+        self.first.contains_key(id)
+    }
+
+    /// The ID to use for a line with ID `id`, reporting it if it is a duplicate. None for a
+    /// duplicate that is not renamed: the line must not be linked to anything.
+    pub(crate) fn check(
+        &mut self,
+        id: Arc<str>,
+        feature_type: Arc<str>,
+        parent: Option<Arc<str>>,
+        location: (&str, usize),
+        errors: &mut Vec<Error>,
+    ) -> Option<Arc<str>> {
+        let (path, line) = location;
+        let Some(first) = self.first.get_mut(&id) else {
+            self.first.insert(id.clone(), FirstUse { feature_type, parent, line, count: 1 });
+            return Some(id);
+        };
+        if first.feature_type == feature_type && first.parent == parent {
+            return Some(id);
+        }
+        first.count += 1;
+        let what = format!(
+            "ID {} of this {} line is already used by the {} on line {}",
+            id, feature_type, first.feature_type, first.line
+        );
+        if !self.rename {
+            errors.push(Error::fatal(Code::DuplicateId, format!("{} (see --rename-duplicates)", what)).for_feature(&*id).at(path, line));
+            return None;
+        }
+        let name: Arc<str> = Arc::from(suffixed(&id, first.count));
+        errors.push(Error::warning(Code::DuplicateId, format!("{}; renamed {}", what, name)).for_feature(&*id).at(path, line));
+        self.renamed.insert(id, name.clone());
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Severity;
+    use crate::gff3::parse_gff3_parallel;
+    use crate::policy::Policy;
+    use crate::transcript_builder::build_transcripts;
+    use std::io::Write;
+
+    #[test]
+    fn test_duplicate_ids_are_reported_or_renamed() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "chr1\t.\tmRNA\t1\t100\t.\t+\t.\tID=tx1;Parent=g1\n\
+             chr1\t.\texon\t1\t10\t.\t+\t.\tID=e1;Parent=tx1\n\
+             chr1\t.\texon\t50\t100\t.\t+\t.\tID=e2;Parent=tx1\n\
+             chr1\t.\tCDS\t5\t10\t.\t+\t0\tID=c1;Parent=tx1\n\
+             chr1\t.\tCDS\t50\t60\t.\t+\t2\tID=c1;Parent=tx1\n\
+             chr1\t.\tmRNA\t1\t100\t.\t+\t.\tID=tx1;Parent=g2\n\
+             chr1\t.\texon\t1\t20\t.\t+\t.\tID=e3;Parent=tx1\n\
+             chr1\t.\texon\t40\t100\t.\t+\t.\tID=e1;Parent=tx1\n"
+        )
+        .unwrap();
+        let path = file.path().to_str().unwrap();
+        let exon = ["exon".to_string()];
+
+        let mut errors = Vec::new();
+        parse_gff3_parallel(path, &exon, &mut errors, 1, false).unwrap();
+        let found: Vec<(Severity, Option<&str>, Option<usize>)> =
+            errors.iter().map(|e| (e.severity, e.feature_id.as_deref(), e.line)).collect();
+        // Without renaming, the last exon has the same parent as the first e1: a multi-line feature
+        assert_eq!(found, [(Severity::Fatal, Some("tx1"), Some(6))]);
+
+        let mut errors = Vec::new();
+        let annotation = parse_gff3_parallel(path, &exon, &mut errors, 1, true).unwrap();
+        assert!(errors.iter().all(|e| e.severity == Severity::Warning && e.code == Code::DuplicateId));
+        let regions: Vec<(&str, &str)> = annotation.regions.iter().map(|r| (&*r.transcript_id, r.region_id.as_str())).collect();
+        assert_eq!(regions, [("tx1", "e1"), ("tx1", "e2"), ("tx1_dup2", "e3"), ("tx1_dup2", "e1_dup2")]);
+
+        // Even when the problem is only a warning, the transcript of the duplicate is left out
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "chr1\t.\texon\t1\t10\t.\t+\t.\tID=e1;Parent=tx1\n\
+             chr1\t.\texon\t50\t100\t.\t+\t.\tID=e2;Parent=tx1\n\
+             chr1\t.\texon\t200\t210\t.\t+\t.\tID=e3;Parent=tx2\n\
+             chr1\t.\texon\t250\t300\t.\t+\t.\tID=e1;Parent=tx2\n"
+        )
+        .unwrap();
+        let mut errors = Vec::new();
+        let annotation = parse_gff3_parallel(file.path().to_str().unwrap(), &exon, &mut errors, 1, false).unwrap();
+        Policy::lenient().judge(&mut errors);
+        assert!(errors.iter().all(|e| e.severity == Severity::Warning));
+        assert!(errors.iter().any(|e| e.code == Code::DuplicateId && e.transcript_id.as_deref() == Some("tx2")));
+        let transcripts = build_transcripts(annotation, &mut errors);
+        let ids: Vec<&str> = transcripts.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["tx1"]);
+
+        let mut names = SequenceNames::new("g.fa", true);
+        let named: Vec<Option<String>> = ["chr1", "chr2", "chr1"].iter().map(|id| names.name(id)).collect();
+        assert_eq!(named, [Some("chr1".into()), Some("chr2".into()), Some("chr1_dup2".into())]);
+    }
+}
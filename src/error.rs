@@ -1,20 +1,321 @@
-#[derive(Debug)]
+use std::fmt;
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Severity {
     Warning,
     Fatal,
 }
 
-#[derive(Debug)]
+/// Stable identifier of each kind of problem, so reports can be counted and triaged
+/// without matching on the message text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Code {
+    InvalidStrand,
+    MissingFeatureId,
+    MissingTranscriptParent,
+    MissingFeatureParent,
+    SynthesizedId,
+    NoRegions,
+    MixedStrand,
+    NegativeWidth,
+    ShortRegion,
+    OverlappingRegions,
+    MultipleChromosomes,
+    SingleRegion,
+    MissingChromosome,
+    InvalidRecord,
+    UnknownTranscript,
+    UnmappedVariant,
+    MissingAllele,
+    OverlappingVariants,
+    SpliceSiteVariant,
+    BoundaryVariant,
+    ReferenceMismatch,
+    DuplicateId,
+    DuplicateSequenceId,
+    ParentMismatch,
+    OutsideParent,
+    TranscriptSpanMismatch,
+    NonCanonicalSpliceSite,
+    MissingStartCodon,
+    MissingStopCodon,
+    InternalStopCodon,
+    CdsLength,
+    InconsistentPhase,
+    QcSkipped,
+    TooManyWarnings,
+}
+
+impl Code {
+    pub const ALL: [Code; 34] = [
+        Code::InvalidStrand,
+        Code::MissingFeatureId,
+        Code::MissingTranscriptParent,
+        Code::MissingFeatureParent,
+        Code::SynthesizedId,
+        Code::NoRegions,
+        Code::MixedStrand,
+        Code::NegativeWidth,
+        Code::ShortRegion,
+        Code::OverlappingRegions,
+        Code::MultipleChromosomes,
+        Code::SingleRegion,
+        Code::MissingChromosome,
+        Code::InvalidRecord,
+        Code::UnknownTranscript,
+        Code::UnmappedVariant,
+        Code::MissingAllele,
+        Code::OverlappingVariants,
+        Code::SpliceSiteVariant,
+        Code::BoundaryVariant,
+        Code::ReferenceMismatch,
+        Code::DuplicateId,
+        Code::DuplicateSequenceId,
+        Code::ParentMismatch,
+        Code::OutsideParent,
+        Code::TranscriptSpanMismatch,
+        Code::NonCanonicalSpliceSite,
+        Code::MissingStartCodon,
+        Code::MissingStopCodon,
+        Code::InternalStopCodon,
+        Code::CdsLength,
+        Code::InconsistentPhase,
+        Code::QcSkipped,
+        Code::TooManyWarnings,
+    ];
+
+    /// The code with this name, such as `MIXED_STRAND`.
+    pub fn from_name(name: &str) -> Option<Code> {
+        Code::ALL.into_iter().find(|c| c.as_str().eq_ignore_ascii_case(name))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Code::InvalidStrand => "INVALID_STRAND",
+            Code::MissingFeatureId => "MISSING_FEATURE_ID",
+            Code::MissingTranscriptParent => "MISSING_TRANSCRIPT_PARENT",
+            Code::MissingFeatureParent => "MISSING_FEATURE_PARENT",
+            Code::SynthesizedId => "SYNTHESIZED_ID",
+            Code::NoRegions => "NO_REGIONS",
+            Code::MixedStrand => "MIXED_STRAND",
+            Code::NegativeWidth => "NEGATIVE_WIDTH",
+            Code::ShortRegion => "SHORT_REGION",
+            Code::OverlappingRegions => "OVERLAPPING_REGIONS",
+            Code::MultipleChromosomes => "MULTIPLE_CHROMOSOMES",
+            Code::SingleRegion => "SINGLE_REGION",
+            Code::MissingChromosome => "MISSING_CHROMOSOME",
+            Code::InvalidRecord => "INVALID_RECORD",
+            Code::UnknownTranscript => "UNKNOWN_TRANSCRIPT",
+            Code::UnmappedVariant => "UNMAPPED_VARIANT",
+            Code::MissingAllele => "MISSING_ALLELE",
+            Code::OverlappingVariants => "OVERLAPPING_VARIANTS",
+            Code::SpliceSiteVariant => "SPLICE_SITE_VARIANT",
+            Code::BoundaryVariant => "BOUNDARY_VARIANT",
+            Code::ReferenceMismatch => "REFERENCE_MISMATCH",
+            Code::DuplicateId => "DUPLICATE_ID",
+            Code::DuplicateSequenceId => "DUPLICATE_SEQUENCE_ID",
+            Code::ParentMismatch => "PARENT_MISMATCH",
+            Code::OutsideParent => "OUTSIDE_PARENT",
+            Code::TranscriptSpanMismatch => "TRANSCRIPT_SPAN_MISMATCH",
+            Code::NonCanonicalSpliceSite => "NON_CANONICAL_SPLICE_SITE",
+            Code::MissingStartCodon => "MISSING_START_CODON",
+            Code::MissingStopCodon => "MISSING_STOP_CODON",
+            Code::InternalStopCodon => "INTERNAL_STOP_CODON",
+            Code::CdsLength => "CDS_LENGTH",
+            Code::InconsistentPhase => "INCONSISTENT_PHASE",
+            Code::QcSkipped => "QC_SKIPPED",
+            Code::TooManyWarnings => "TOO_MANY_WARNINGS",
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Error {
     pub severity: Severity,
+    pub code: Code,
     pub message: String,
+    pub transcript_id: Option<String>,
+    pub gene_id: Option<String>,
+    pub feature_id: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<usize>,
 }
 
 impl Error {
-    pub fn warning(msg: impl Into<String>) -> Self {
-        Self { severity: Severity::Warning, message: msg.into() }
+    /// Whether the problem is why its transcript was left out: a fatal problem, or a single
+    /// feature, for which the transcript is skipped.
+    pub fn rejects_transcript(&self) -> bool {
+        self.severity == Severity::Fatal || self.code == Code::SingleRegion
+    }
+
+    fn new(severity: Severity, code: Code, message: String) -> Self {
+        Self { severity, code, message, transcript_id: None, gene_id: None, feature_id: None, file: None, line: None }
+    }
+    pub fn warning(code: Code, msg: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, msg.into())
+    }
+    pub fn fatal(code: Code, msg: impl Into<String>) -> Self {
+        Self::new(Severity::Fatal, code, msg.into())
+    }
+    /// Attach the ID of the transcript this problem concerns.
+    pub fn for_transcript(mut self, transcript_id: impl Into<String>) -> Self {
+        self.transcript_id = Some(transcript_id.into());
+        self
+    }
+    /// Attach the ID of the gene this problem concerns.
+    pub fn for_gene(mut self, gene_id: impl Into<String>) -> Self {
+        self.gene_id = Some(gene_id.into());
+        self
+    }
+    /// Attach the ID of the feature (exon, CDS, ...) this problem concerns.
+    pub fn for_feature(mut self, feature_id: impl Into<String>) -> Self {
+        self.feature_id = Some(feature_id.into());
+        self
+    }
+    /// Attach the file and 1-based line the problem was found at.
+    pub fn at(mut self, file: impl Into<String>, line: usize) -> Self {
+        self.file = Some(file.into());
+        self.line = Some(line);
+        self
+    }
+}
+
+/// Layout of the problem report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// `[Severity] CODE: message`, one per line.
+    Text,
+    /// One JSON object per line.
+    JsonLines,
+    /// Tab-separated columns with a header line.
+    Tsv,
+}
+
+impl ReportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(ReportFormat::Text),
+            "jsonl" => Some(ReportFormat::JsonLines),
+            "tsv" => Some(ReportFormat::Tsv),
+            _ => None,
+        }
     }
-    pub fn fatal(msg: impl Into<String>) -> Self {
-        Self { severity: Severity::Fatal, message: msg.into() }
+}
+
+/// Write problems one per line, as `[Severity] CODE: message`.
+pub fn write_errors(errors: &[Error], out: &mut impl Write) -> std::io::Result<()> {
+    write_report(errors, ReportFormat::Text, out)
+}
+
+/// Write problems in the given format. The JSON Lines and TSV reports have the fields
+/// severity, code, transcript, gene, feature, file, line and message; absent fields are
+/// null in JSON and empty in TSV.
+pub fn write_report(errors: &[Error], format: ReportFormat, out: &mut impl Write) -> std::io::Result<()> {
+    if format == ReportFormat::Tsv {
+        writeln!(out, "severity\tcode\ttranscript\tgene\tfeature\tfile\tline\tmessage")?;
+    }
+    for e in errors {
+        match format {
+            ReportFormat::Text => writeln!(out, "[{:?}] {}: {}", e.severity, e.code, e.message)?,
+            ReportFormat::JsonLines => {
+                let string = |s: &Option<String>| s.as_deref().map_or("null".to_string(), json_string);
+                writeln!(
+                    out,
+                    "{{\"severity\":\"{:?}\",\"code\":\"{}\",\"transcript\":{},\"gene\":{},\"feature\":{},\"file\":{},\"line\":{},\"message\":{}}}",
+                    e.severity,
+                    e.code,
+                    string(&e.transcript_id),
+                    string(&e.gene_id),
+                    string(&e.feature_id),
+                    string(&e.file),
+                    e.line.map_or("null".to_string(), |l| l.to_string()),
+                    json_string(&e.message)
+                )?
+            }
+            ReportFormat::Tsv => {
+                let field = |s: &Option<String>| s.as_deref().map(tsv_field).unwrap_or_default();
+                writeln!(
+                    out,
+                    "{:?}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    e.severity,
+                    e.code,
+                    field(&e.transcript_id),
+                    field(&e.gene_id),
+                    field(&e.feature_id),
+                    field(&e.file),
+                    e.line.map(|l| l.to_string()).unwrap_or_default(),
+                    tsv_field(&e.message)
+                )?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A JSON string literal.
+pub(crate) fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// A TSV field, with tabs and line breaks replaced by spaces.
+fn tsv_field(s: &str) -> String {
+    s.replace(['\t', '\n', '\r'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_formats() {
+        let errors = vec![
+            Error::fatal(Code::MixedStrand, "Transcript t1 has \"mixed\" strands.").for_transcript("t1").for_gene("g1").at("a.gff3", 12),
+            Error::warning(Code::InvalidRecord, "bad\tline"),
+        ];
+
+        let mut text = Vec::new();
+        write_report(&errors, ReportFormat::Text, &mut text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "[Fatal] MIXED_STRAND: Transcript t1 has \"mixed\" strands.\n[Warning] INVALID_RECORD: bad\tline\n"
+        );
+
+        let mut jsonl = Vec::new();
+        write_report(&errors, ReportFormat::JsonLines, &mut jsonl).unwrap();
+        let jsonl = String::from_utf8(jsonl).unwrap();
+        assert_eq!(
+            jsonl.lines().next().unwrap(),
+            "{\"severity\":\"Fatal\",\"code\":\"MIXED_STRAND\",\"transcript\":\"t1\",\"gene\":\"g1\",\"feature\":null,\
+             \"file\":\"a.gff3\",\"line\":12,\"message\":\"Transcript t1 has \\\"mixed\\\" strands.\"}"
+        );
+        assert!(jsonl.lines().nth(1).unwrap().ends_with("\"line\":null,\"message\":\"bad\\tline\"}"));
+
+        let mut tsv = Vec::new();
+        write_report(&errors, ReportFormat::Tsv, &mut tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let rows: Vec<&str> = tsv.lines().collect();
+        assert_eq!(rows[1], "Fatal\tMIXED_STRAND\tt1\tg1\t\ta.gff3\t12\tTranscript t1 has \"mixed\" strands.");
+        assert_eq!(rows[2], "Warning\tINVALID_RECORD\t\t\t\t\t\tbad line");
     }
 }
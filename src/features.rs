@@ -0,0 +1,157 @@
+use crate::genome::GenomeSource;
+use crate::parallel::for_each_in_order;
+use crate::structures::{Region, Strand, Transcript};
+use anyhow::Result;
+use bio::alphabets::dna;
+use bio::io::fasta;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Write every region of every transcript as its own FASTA record, on the transcript strand.
+/// Records are named by the region ID, with `_seg{n}` for the segments of a discontinuous
+/// feature; the description gives the transcript and the location.
+pub fn write_feature_sequences(
+    genome: &dyn GenomeSource,
+    transcripts: &[Transcript],
+    output_fasta_path: &str,
+    threads: usize,
+) -> Result<()> {
+    let mut writer = fasta::Writer::to_file(output_fasta_path)?;
+    for_each_in_order(
+        transcripts,
+        threads,
+        |transcript| -> Result<Vec<Vec<u8>>> {
+            transcript
+                .regions
+                .iter()
+                .map(|region| extract_region_sequence(genome, &transcript.chromosome, region))
+                .collect()
+        },
+        |transcript, seqs| {
+            for (region, seq) in transcript.regions.iter().zip(seqs?) {
+                let description = format!("{} {}", transcript.id, location(&transcript.chromosome, region));
+                writer.write(&transcript.region_label(region), Some(&description), &seq)?;
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Write each distinct region (same chromosome, coordinates and strand) once, named as in
+/// the first transcript using it. The optional TSV lists the transcripts sharing it.
+pub fn write_unique_feature_sequences(
+    genome: &dyn GenomeSource,
+    transcripts: &[Transcript],
+    output_fasta_path: &str,
+    map_path: Option<&str>,
+    threads: usize,
+) -> Result<()> {
+    let mut order: Vec<(&str, &Region, String)> = Vec::new();
+    let mut users: HashMap<(&str, usize, usize, Strand), Vec<&str>> = HashMap::new();
+    for transcript in transcripts {
+        for region in &transcript.regions {
+            let key = (transcript.chromosome.as_str(), region.start, region.end, region.strand);
+            let entry = users.entry(key).or_default();
+            if entry.is_empty() {
+                order.push((transcript.chromosome.as_str(), region, transcript.region_label(region)));
+            }
+            entry.push(transcript.id.as_str());
+        }
+    }
+
+    let mut writer = fasta::Writer::to_file(output_fasta_path)?;
+    let mut map = match map_path {
+        Some(path) => {
+            let mut map = BufWriter::new(File::create(path)?);
+            writeln!(map, "feature_id\tchromosome\tstart\tend\tstrand\ttranscript_ids")?;
+            Some(map)
+        }
+        None => None,
+    };
+
+    for_each_in_order(
+        &order,
+        threads,
+        |(chromosome, region, _)| extract_region_sequence(genome, chromosome, region),
+        |(chromosome, region, label), seq| {
+            writer.write(label, Some(&location(chromosome, region)), &seq?)?;
+            if let Some(map) = map.as_mut() {
+                let transcripts = &users[&(*chromosome, region.start, region.end, region.strand)];
+                writeln!(
+                    map,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    label,
+                    chromosome,
+                    region.start,
+                    region.end,
+                    region.strand,
+                    transcripts.join(",")
+                )?;
+            }
+            Ok(())
+        },
+    )?;
+
+    if let Some(mut map) = map {
+        map.flush()?;
+    }
+    Ok(())
+}
+
+/// Sequence of a single region, reverse-complemented on the minus strand.
+pub fn extract_region_sequence(
+    genome: &dyn GenomeSource,
+    chromosome: &str,
+    region: &Region,
+) -> Result<Vec<u8>> {
+    let mut seq = Vec::with_capacity(region.end + 1 - region.start);
+    genome.fetch(chromosome, region.start, region.end, &mut seq)?;
+    Ok(match region.strand {
+        Strand::Plus => seq,
+        Strand::Minus => dna::revcomp(seq),
+    })
+}
+
+fn location(chromosome: &str, region: &Region) -> String {
+    format!("{}:{}-{}({})", chromosome, region.start, region.end, region.strand)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unique_features_shared_by_isoforms() {
+        let dir = tempfile::tempdir().unwrap();
+        let fasta_path = dir.path().join("exons.fa");
+        let map_path = dir.path().join("exons.tsv");
+        let genome = HashMap::from([("chr1".to_string(), b"AACCGGTTAC".to_vec())]);
+        let transcripts = vec![
+            Transcript::test("tx1", "chr1", Strand::Minus, &[(7, 10), (1, 3)]).region_ids(&["a", "b"]),
+            Transcript::test("tx2", "chr1", Strand::Minus, &[(7, 10), (4, 5)]).region_ids(&["c", "d"]),
+        ];
+        write_unique_feature_sequences(
+            &genome,
+            &transcripts,
+            fasta_path.to_str().unwrap(),
+            Some(map_path.to_str().unwrap()),
+            2,
+        )
+        .unwrap();
+
+        let records: Vec<(String, Vec<u8>)> = fasta::Reader::from_file(&fasta_path)
+            .unwrap()
+            .records()
+            .map(|r| r.unwrap())
+            .map(|r| (r.id().to_string(), r.seq().to_vec()))
+            .collect();
+        assert_eq!(records, vec![
+            ("a".to_string(), b"GTAA".to_vec()),
+            ("b".to_string(), b"GTT".to_vec()),
+            ("d".to_string(), b"CG".to_vec()),
+        ]);
+        let map = std::fs::read_to_string(&map_path).unwrap();
+        assert_eq!(map.lines().nth(1), Some("a\tchr1\t7\t10\t-\ttx1,tx2"));
+    }
+}
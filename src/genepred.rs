@@ -0,0 +1,88 @@
+use crate::structures::Transcript;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Write transcripts in the UCSC genePred format (10 columns), in the order given.
+pub fn write_genepred(transcripts: &[Transcript], out_path: &str) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(out_path)?);
+    for transcript in transcripts {
+        writeln!(writer, "{}", genepred_line(transcript))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write transcripts in the refFlat format used by Picard: genePred prefixed with the gene name.
+pub fn write_refflat(transcripts: &[Transcript], out_path: &str) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(out_path)?);
+    for transcript in transcripts {
+        writeln!(writer, "{}", refflat_line(transcript))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Format a transcript as a genePred line (0-based starts, 1-based ends, comma-terminated lists).
+pub fn genepred_line(transcript: &Transcript) -> String {
+    let tx_start = transcript.start() - 1;
+    let tx_end = transcript.end();
+
+    // Non-coding transcripts get an empty CDS at txEnd, as UCSC tools expect.
+    let (cds_start, cds_end) = match transcript.cds {
+        Some((start, end)) => (start - 1, end),
+        None => (tx_end, tx_end),
+    };
+
+    let exons = transcript.ascending_regions();
+    let starts: String = exons.iter().map(|r| format!("{},", r.start - 1)).collect();
+    let ends: String = exons.iter().map(|r| format!("{},", r.end)).collect();
+
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+        transcript.id,
+        transcript.chromosome,
+        transcript.strand(),
+        tx_start,
+        tx_end,
+        cds_start,
+        cds_end,
+        exons.len(),
+        starts,
+        ends
+    )
+}
+
+/// Format a transcript as a refFlat line. The gene name falls back to the gene ID,
+/// then to the transcript ID.
+pub fn refflat_line(transcript: &Transcript) -> String {
+    let gene_name = transcript
+        .gene_name
+        .as_deref()
+        .or(transcript.gene_id.as_deref())
+        .unwrap_or(&transcript.id);
+    format!("{}\t{}", gene_name, genepred_line(transcript))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structures::Strand;
+
+    fn transcript() -> Transcript {
+        Transcript::test("tx1", "chr1", Strand::Minus, &[(201, 260), (101, 150)])
+            .region_ids(&["e2", "e1"])
+            .gene("g1", Some("ACT1"))
+    }
+
+    #[test]
+    fn test_refflat_coding() {
+        let line = refflat_line(&transcript().coding(Some((121, 230))));
+        assert_eq!(line, "ACT1\ttx1\tchr1\t-\t100\t260\t120\t230\t2\t100,200,\t150,260,");
+    }
+
+    #[test]
+    fn test_genepred_noncoding() {
+        let line = genepred_line(&transcript());
+        assert_eq!(line, "tx1\tchr1\t-\t100\t260\t260\t260\t2\t100,200,\t150,260,");
+    }
+}
@@ -0,0 +1,628 @@
+use crate::duplicates::SequenceNames;
+use crate::error::Error;
+use crate::packed::PackedGenome;
+use crate::transcript_builder::load_genome_to_memory;
+use crate::twobit::{is_twobit, TwoBitGenome};
+use anyhow::{Context, Result};
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Source of genome sequence for extraction. Positions are 1-based and inclusive.
+pub trait GenomeSource: Sync {
+    /// Append the bases `start..=end` of a sequence to `out`.
+    fn fetch(&self, chromosome: &str, start: usize, end: usize, out: &mut Vec<u8>) -> Result<()>;
+
+    /// Length of a sequence, or None if the genome does not contain it.
+    fn length(&self, chromosome: &str) -> Option<usize>;
+}
+
+/// The whole genome loaded into memory, as returned by `load_genome_to_memory`.
+impl GenomeSource for HashMap<String, Vec<u8>> {
+    fn fetch(&self, chromosome: &str, start: usize, end: usize, out: &mut Vec<u8>) -> Result<()> {
+        let sequence = self.get(chromosome).ok_or_else(|| missing_chromosome(chromosome))?;
+        let bases = start
+            .checked_sub(1)
+            .and_then(|s| sequence.get(s..end))
+            .ok_or_else(|| out_of_range(chromosome, start, end))?;
+        out.extend_from_slice(bases);
+        Ok(())
+    }
+
+    fn length(&self, chromosome: &str) -> Option<usize> {
+        self.get(chromosome).map(|s| s.len())
+    }
+}
+
+pub(crate) fn missing_chromosome(chromosome: &str) -> anyhow::Error {
+    anyhow::anyhow!("Chromosome '{}' not found in genome.", chromosome)
+}
+
+pub(crate) fn out_of_range(chromosome: &str, start: usize, end: usize) -> anyhow::Error {
+    anyhow::anyhow!("Region {}..{} is past the end of chromosome '{}'.", start, end, chromosome)
+}
+
+/// Open a genome FASTA for extraction. With a `.fai` index next to it, sequences are read
+/// on demand; BGZF-compressed files also need their `.gzi` index. Otherwise the whole
+/// genome is loaded into memory. UCSC `.2bit` files are always read on demand. Of sequences
+/// with the same name, the first is used.
+pub fn open_genome(fasta_path: &str) -> Result<Box<dyn GenomeSource>> {
+    open_genome_with(fasta_path, false, false, &mut Vec::new())
+}
+
+/// Whether `open_genome` reads the genome on demand, through a `.fai` index or as `.2bit`,
+/// rather than loading it into memory.
+pub fn reads_on_demand(fasta_path: &str) -> Result<bool> {
+    Ok(is_twobit(fasta_path)? || Path::new(&format!("{}.fai", fasta_path)).exists())
+}
+
+/// Like `open_genome`, but a genome loaded into memory is stored packed, 2 bits per base,
+/// if `packed` is set; `packed` has no effect on a genome read on demand. Repeated sequence
+/// names are reported to `errors`, and renamed if `rename_duplicates` is set.
+pub fn open_genome_with(
+    fasta_path: &str,
+    packed: bool,
+    rename_duplicates: bool,
+    errors: &mut Vec<Error>,
+) -> Result<Box<dyn GenomeSource>> {
+    let mut names = SequenceNames::new(fasta_path, rename_duplicates);
+    let fai_path = format!("{}.fai", fasta_path);
+    let genome: Box<dyn GenomeSource> = if is_twobit(fasta_path)? {
+        Box::new(TwoBitGenome::open(fasta_path, &mut names)?)
+    } else if !Path::new(&fai_path).exists() {
+        if packed {
+            Box::new(PackedGenome::load(fasta_path, &mut names)?)
+        } else {
+            Box::new(load_genome_to_memory(fasta_path, &mut names)?)
+        }
+    } else {
+        open_indexed(fasta_path, read_fai(&fai_path, &mut names)?)?
+    };
+    names.finish(errors);
+    Ok(genome)
+}
+
+fn open_indexed(fasta_path: &str, index: HashMap<String, FaiEntry>) -> Result<Box<dyn GenomeSource>> {
+    if is_bgzf(fasta_path)? {
+        let gzi_path = format!("{}.gzi", fasta_path);
+        let reader = BgzfReader::open(fasta_path, &gzi_path)?;
+        Ok(Box::new(IndexedFasta::new(index, reader)))
+    } else {
+        Ok(Box::new(IndexedFasta::new(index, File::open(fasta_path)?)))
+    }
+}
+
+/// One line of a samtools `.fai` index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaiEntry {
+    pub length: usize,
+    pub offset: u64,
+    pub line_bases: usize,
+    pub line_width: usize,
+}
+
+impl FaiEntry {
+    /// Byte offset of the 0-based base `i`, in the uncompressed file.
+    fn position(&self, i: usize) -> u64 {
+        self.offset + ((i / self.line_bases) * self.line_width + i % self.line_bases) as u64
+    }
+}
+
+/// Read a samtools `.fai` index, naming its sequences through `names`.
+pub fn read_fai(fai_path: &str, names: &mut SequenceNames) -> Result<HashMap<String, FaiEntry>> {
+    let reader = BufReader::new(File::open(fai_path)?);
+    let mut index = HashMap::new();
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        let cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() < 5 {
+            anyhow::bail!("{}:{} is not a .fai line", fai_path, line_no + 1);
+        }
+        let field = |i: usize| {
+            cols[i]
+                .parse::<u64>()
+                .with_context(|| format!("{}:{} has an invalid number", fai_path, line_no + 1))
+        };
+        let entry = FaiEntry {
+            length: field(1)? as usize,
+            offset: field(2)?,
+            line_bases: field(3)? as usize,
+            line_width: field(4)? as usize,
+        };
+        if entry.line_bases == 0 && entry.length > 0 {
+            anyhow::bail!("{}:{} has zero bases per line", fai_path, line_no + 1);
+        }
+        if let Some(name) = names.name(cols[0]) {
+            index.insert(name, entry);
+        }
+    }
+    Ok(index)
+}
+
+/// Byte-level random access into the uncompressed content of a file. Reads take `&self`
+/// so that worker threads can read concurrently.
+pub trait ByteSource: Send + Sync {
+    fn read_range(&self, offset: u64, out: &mut [u8]) -> Result<()>;
+}
+
+/// Positioned reads, which leave the file cursor alone so that they can run in parallel.
+impl ByteSource for File {
+    #[cfg(unix)]
+    fn read_range(&self, offset: u64, out: &mut [u8]) -> Result<()> {
+        std::os::unix::fs::FileExt::read_exact_at(self, out, offset)?;
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn read_range(&self, offset: u64, out: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < out.len() {
+            match std::os::windows::fs::FileExt::seek_read(self, &mut out[done..], offset + done as u64)? {
+                0 => return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
+                n => done += n,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads a `ByteSource` sequentially from an offset.
+struct ReaderAt<'a, R: ByteSource> {
+    source: &'a R,
+    offset: u64,
+}
+
+impl<R: ByteSource> Read for ReaderAt<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.source.read_range(self.offset, buf).map_err(|e| match e.downcast::<std::io::Error>() {
+            Ok(e) => e,
+            Err(e) => std::io::Error::other(e),
+        })?;
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+}
+
+/// FASTA read through its `.fai` index, so that only the requested bases are loaded.
+pub struct IndexedFasta<R: ByteSource> {
+    index: HashMap<String, FaiEntry>,
+    reader: R,
+}
+
+impl<R: ByteSource> IndexedFasta<R> {
+    pub fn new(index: HashMap<String, FaiEntry>, reader: R) -> Self {
+        Self { index, reader }
+    }
+}
+
+impl<R: ByteSource> GenomeSource for IndexedFasta<R> {
+    fn fetch(&self, chromosome: &str, start: usize, end: usize, out: &mut Vec<u8>) -> Result<()> {
+        let entry = self.index.get(chromosome).ok_or_else(|| missing_chromosome(chromosome))?;
+        if start == 0 || end > entry.length {
+            return Err(out_of_range(chromosome, start, end));
+        }
+        if start > end {
+            return Ok(());
+        }
+        let first = entry.position(start - 1);
+        let last = entry.position(end - 1);
+        let mut raw = vec![0; (last - first + 1) as usize];
+        self.reader.read_range(first, &mut raw)?;
+        out.extend(raw.into_iter().filter(|b| *b != b'\n' && *b != b'\r'));
+        Ok(())
+    }
+
+    fn length(&self, chromosome: &str) -> Option<usize> {
+        self.index.get(chromosome).map(|e| e.length)
+    }
+}
+
+/// Check the gzip magic and the BGZF `BC` extra field at the start of a file.
+pub fn is_bgzf(path: &str) -> Result<bool> {
+    let mut header = [0u8; 16];
+    let mut file = File::open(path)?;
+    let mut read = 0;
+    while read < header.len() {
+        match file.read(&mut header[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read == header.len() && header[..4] == [0x1f, 0x8b, 8, 4] && header[12..14] == *b"BC")
+}
+
+/// Build the samtools-compatible `.fai` of a genome FASTA, and the `.gzi` if it is BGZF-compressed,
+/// replacing any existing index.
+pub fn build_index(fasta_path: &str) -> Result<()> {
+    write_index(fasta_path, true, true)
+}
+
+/// Build the `.fai` and `.gzi` indexes of a genome FASTA only where they are missing.
+pub fn ensure_index(fasta_path: &str) -> Result<()> {
+    let fai = !Path::new(&format!("{}.fai", fasta_path)).exists();
+    let gzi = !Path::new(&format!("{}.gzi", fasta_path)).exists();
+    write_index(fasta_path, fai, gzi)
+}
+
+fn write_index(fasta_path: &str, fai: bool, gzi: bool) -> Result<()> {
+    let bgzf = is_bgzf(fasta_path)?;
+    if fai {
+        let index = if bgzf {
+            index_fasta(BufReader::new(MultiGzDecoder::new(File::open(fasta_path)?)), fasta_path)?
+        } else {
+            index_fasta(BufReader::new(File::open(fasta_path)?), fasta_path)?
+        };
+        write_fai(&index, &format!("{}.fai", fasta_path))?;
+    }
+    if bgzf && gzi {
+        write_gzi(&build_gzi(fasta_path)?, &format!("{}.gzi", fasta_path))?;
+    }
+    Ok(())
+}
+
+/// Index the (uncompressed) content of a FASTA, in file order. All lines of a sequence but
+/// the last must have the same length, otherwise the sequence cannot be indexed.
+pub fn index_fasta(mut reader: impl BufRead, fasta_path: &str) -> Result<Vec<(String, FaiEntry)>> {
+    let mut index: Vec<(String, FaiEntry)> = Vec::new();
+    let mut line = Vec::new();
+    let mut offset = 0u64;
+    let mut line_no = 0;
+    let mut short_line = false; // a line shorter than the first one was seen in this sequence
+
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        line_no += 1;
+        offset += read as u64;
+
+        if line[0] == b'>' {
+            let header = String::from_utf8_lossy(&line[1..]);
+            let name = header.split_whitespace().next().unwrap_or("").to_string();
+            index.push((name, FaiEntry { length: 0, offset, line_bases: 0, line_width: 0 }));
+            short_line = false;
+            continue;
+        }
+
+        let Some((name, entry)) = index.last_mut() else {
+            if line.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+            anyhow::bail!("{}:{}: sequence data before the first '>' header", fasta_path, line_no);
+        };
+        let bases = line.iter().filter(|b| **b != b'\n' && **b != b'\r').count();
+        if entry.line_bases == 0 && entry.length == 0 {
+            entry.line_bases = bases;
+            entry.line_width = read;
+        } else if bases > 0 && (short_line || bases > entry.line_bases || (bases == entry.line_bases && read != entry.line_width)) {
+            anyhow::bail!(
+                "{}:{}: irregular line length in sequence '{}'; all lines but the last must have {} bases. \
+                 Rewrap the FASTA (for example with 'seqkit seq -w 60') to index it.",
+                fasta_path,
+                line_no,
+                name,
+                entry.line_bases
+            );
+        }
+        if bases < entry.line_bases {
+            short_line = true;
+        }
+        entry.length += bases;
+    }
+
+    Ok(index)
+}
+
+fn write_fai(index: &[(String, FaiEntry)], fai_path: &str) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(fai_path)?);
+    for (name, e) in index {
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", name, e.length, e.offset, e.line_bases, e.line_width)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Scan the blocks of a BGZF file for its `.gzi` index, without decompressing them.
+/// Like `read_gzi`, the result starts with the implicit first block at (0, 0).
+pub fn build_gzi(path: &str) -> Result<Vec<(u64, u64)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut blocks = vec![(0, 0)];
+    let (mut compressed, mut uncompressed) = (0u64, 0u64);
+    let mut header = [0u8; 18];
+    let truncated = |offset| move || format!("{} has a truncated BGZF block at offset {}", path, offset);
+    loop {
+        if reader.fill_buf()?.is_empty() {
+            break;
+        }
+        reader.read_exact(&mut header).with_context(truncated(compressed))?;
+        if header[..4] != [0x1f, 0x8b, 8, 4] || header[12..14] != *b"BC" {
+            anyhow::bail!("{} is not BGZF at offset {}", path, compressed);
+        }
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as u64 + 1;
+        let extra_length = u16::from_le_bytes([header[10], header[11]]) as u64;
+        // The header, the extra field, and the CRC and size trailer must fit in the block
+        if extra_length < 6 || block_size < 12 + extra_length + 8 {
+            anyhow::bail!("{} has an invalid BGZF block at offset {}", path, compressed);
+        }
+        let trailer_at = block_size - 4 - 18;
+        reader.seek_relative(trailer_at as i64)?;
+        let mut size = [0u8; 4];
+        reader.read_exact(&mut size).with_context(truncated(compressed))?;
+        compressed += block_size;
+        uncompressed += u32::from_le_bytes(size) as u64;
+        blocks.push((compressed, uncompressed));
+    }
+    // Entries at the end of the data start no block with content (the last one is the end of
+    // the file, the others are empty blocks such as the EOF marker)
+    while blocks.len() > 1 && blocks[blocks.len() - 1].1 == uncompressed {
+        blocks.pop();
+    }
+    Ok(blocks)
+}
+
+fn write_gzi(blocks: &[(u64, u64)], gzi_path: &str) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(gzi_path)?);
+    let entries = &blocks[1..];
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (c, u) in entries {
+        writer.write_all(&c.to_le_bytes())?;
+        writer.write_all(&u.to_le_bytes())?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a `.gzi` index: pairs of (compressed, uncompressed) offsets of BGZF block starts.
+/// The first block, at (0, 0), is implicit in the file and added here.
+pub fn read_gzi(gzi_path: &str) -> Result<Vec<(u64, u64)>> {
+    let mut bytes = Vec::new();
+    File::open(gzi_path)
+        .with_context(|| format!("BGZF genome needs its index {}", gzi_path))?
+        .read_to_end(&mut bytes)?;
+    let number = |i: usize| -> Result<u64> {
+        let chunk = bytes
+            .get(i * 8..i * 8 + 8)
+            .ok_or_else(|| anyhow::anyhow!("{} is truncated", gzi_path))?;
+        Ok(u64::from_le_bytes(chunk.try_into().unwrap()))
+    };
+    let count = number(0)? as usize;
+    let mut blocks = vec![(0, 0)];
+    for i in 0..count {
+        blocks.push((number(1 + 2 * i)?, number(2 + 2 * i)?));
+    }
+    Ok(blocks)
+}
+
+/// Random access into a BGZF file through its `.gzi` index. The last few decompressed
+/// blocks are kept, as consecutive exons are usually close to each other.
+pub struct BgzfReader {
+    file: File,
+    blocks: Vec<(u64, u64)>,
+    cached: Mutex<Vec<(u64, Arc<Vec<u8>>)>>, // uncompressed start of each block, and its content
+}
+
+/// Decompressed blocks kept by a `BgzfReader`, enough for one per worker thread.
+const CACHED_BLOCKS: usize = 32;
+
+impl BgzfReader {
+    pub fn open(path: &str, gzi_path: &str) -> Result<Self> {
+        Ok(Self { file: File::open(path)?, blocks: read_gzi(gzi_path)?, cached: Mutex::new(Vec::new()) })
+    }
+
+    /// Decompress the block at a compressed offset, returning its content and total size.
+    fn read_block(&self, offset: u64) -> Result<(Vec<u8>, u64)> {
+        let (data, size) = read_bgzf_block(&mut ReaderAt { source: &self.file, offset })?
+            .ok_or_else(|| anyhow::anyhow!("Unexpected end of BGZF file at offset {}", offset))?;
+        Ok((data, size))
+    }
+
+    /// The block holding the uncompressed position `pos`, and its uncompressed start.
+    /// Blocks are decompressed outside the lock so that threads do not wait on each other.
+    fn load(&self, pos: u64) -> Result<(u64, Arc<Vec<u8>>)> {
+        let lock = || self.cached.lock().map_err(|_| anyhow::anyhow!("BGZF block cache poisoned"));
+        if let Some((start, data)) = lock()?.iter().find(|(start, data)| pos >= *start && pos < start + data.len() as u64) {
+            return Ok((*start, data.clone()));
+        }
+        let i = self.blocks.partition_point(|&(_, u)| u <= pos) - 1;
+        let (mut compressed, mut uncompressed) = self.blocks[i];
+        loop {
+            let (data, size) = self.read_block(compressed)?;
+            if data.is_empty() {
+                anyhow::bail!("Position {} is past the end of the BGZF file", pos);
+            }
+            if pos < uncompressed + data.len() as u64 {
+                let block = (uncompressed, Arc::new(data));
+                let mut cached = lock()?;
+                if cached.len() == CACHED_BLOCKS {
+                    cached.remove(0);
+                }
+                cached.push(block.clone());
+                return Ok(block);
+            }
+            compressed += size;
+            uncompressed += data.len() as u64;
+        }
+    }
+}
+
+impl ByteSource for BgzfReader {
+    fn read_range(&self, offset: u64, out: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < out.len() {
+            let pos = offset + done as u64;
+            let (start, data) = self.load(pos)?;
+            let from = (pos - start) as usize;
+            let n = (data.len() - from).min(out.len() - done);
+            out[done..done + n].copy_from_slice(&data[from..from + n]);
+            done += n;
+        }
+        Ok(())
+    }
+}
+
+/// Read one BGZF block from the current position: its decompressed content and its
+/// compressed size. Returns None at the end of the file.
+pub fn read_bgzf_block(reader: &mut impl Read) -> Result<Option<(Vec<u8>, u64)>> {
+    let mut header = [0u8; 12];
+    match reader.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    if header[..4] != [0x1f, 0x8b, 8, 4] {
+        anyhow::bail!("Not a BGZF block");
+    }
+    let extra_length = u16::from_le_bytes([header[10], header[11]]) as usize;
+    let mut extra = vec![0u8; extra_length];
+    reader.read_exact(&mut extra)?;
+
+    let mut block_size = None;
+    let mut i = 0;
+    while i + 4 <= extra.len() {
+        let length = u16::from_le_bytes([extra[i + 2], extra[i + 3]]) as usize;
+        if extra[i..i + 2] == *b"BC" && length == 2 && i + 6 <= extra.len() {
+            block_size = Some(u16::from_le_bytes([extra[i + 4], extra[i + 5]]) as usize + 1);
+        }
+        i += 4 + length;
+    }
+    let block_size = block_size.ok_or_else(|| anyhow::anyhow!("BGZF block without size"))?;
+
+    let remaining = block_size
+        .checked_sub(12 + extra_length)
+        .filter(|r| *r >= 8)
+        .ok_or_else(|| anyhow::anyhow!("Invalid BGZF block size"))?;
+    let mut compressed = vec![0u8; remaining];
+    reader.read_exact(&mut compressed)?;
+    let (deflated, trailer) = compressed.split_at(remaining - 8);
+    let expected = u32::from_le_bytes(trailer[4..8].try_into().unwrap()) as usize;
+
+    let mut data = Vec::with_capacity(expected);
+    DeflateDecoder::new(deflated).read_to_end(&mut data)?;
+    if data.len() != expected {
+        anyhow::bail!("Corrupt BGZF block");
+    }
+    Ok(Some((data, block_size as u64)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+
+    /// Compress into BGZF blocks of at most `block` input bytes, with the EOF marker,
+    /// returning the file content and the `.gzi` content.
+    pub(crate) fn bgzf(content: &[u8], block: usize) -> (Vec<u8>, Vec<u8>) {
+        let mut out = Vec::new();
+        let mut entries = Vec::new();
+        let mut uncompressed = 0;
+        for chunk in content.chunks(block).chain(std::iter::once(&[][..])) {
+            if uncompressed > 0 && !chunk.is_empty() {
+                entries.push((out.len() as u64, uncompressed as u64));
+            }
+            let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(chunk).unwrap();
+            let deflated = encoder.finish().unwrap();
+            let size = (12 + 6 + deflated.len() + 8 - 1) as u16;
+            out.extend_from_slice(&[0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0]);
+            out.extend_from_slice(&size.to_le_bytes());
+            out.extend_from_slice(&deflated);
+            let mut crc = flate2::Crc::new();
+            crc.update(chunk);
+            out.extend_from_slice(&crc.sum().to_le_bytes());
+            out.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            uncompressed += chunk.len();
+        }
+        let mut gzi = (entries.len() as u64).to_le_bytes().to_vec();
+        for (c, u) in entries {
+            gzi.extend_from_slice(&c.to_le_bytes());
+            gzi.extend_from_slice(&u.to_le_bytes());
+        }
+        (out, gzi)
+    }
+
+    const FASTA: &[u8] = b">chr1 first\nACGTA\nCCGGT\nTA\n>chr2\nGGGG\n";
+    const FAI: &str = "chr1\t12\t12\t5\t6\nchr2\t4\t33\t4\t5\n";
+
+    #[test]
+    fn test_indexed_fasta_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("g.fa");
+        std::fs::write(&path, FASTA).unwrap();
+        std::fs::write(dir.path().join("g.fa.fai"), FAI).unwrap();
+        let genome = open_genome(path.to_str().unwrap()).unwrap();
+        let mut out = Vec::new();
+        genome.fetch("chr1", 4, 11, &mut out).unwrap();
+        assert_eq!(out, b"TACCGGTT");
+        assert_eq!(genome.length("chr2"), Some(4));
+        assert!(genome.fetch("chr1", 10, 13, &mut out).is_err());
+        assert!(genome.fetch("chr3", 1, 1, &mut out).is_err());
+    }
+
+    #[test]
+    fn test_bgzf_fasta_fetch_across_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("g.fa.gz");
+        let (compressed, gzi) = bgzf(FASTA, 7);
+        std::fs::write(&path, compressed).unwrap();
+        std::fs::write(dir.path().join("g.fa.gz.fai"), FAI).unwrap();
+        std::fs::write(dir.path().join("g.fa.gz.gzi"), gzi).unwrap();
+        let genome = open_genome(path.to_str().unwrap()).unwrap();
+        let mut out = Vec::new();
+        genome.fetch("chr1", 1, 12, &mut out).unwrap();
+        genome.fetch("chr2", 2, 4, &mut out).unwrap();
+        assert_eq!(out, b"ACGTACCGGTTAGGG");
+    }
+
+    #[test]
+    fn test_index_fasta_matches_samtools() {
+        let index = index_fasta(FASTA, "g.fa").unwrap();
+        let fai: String = index
+            .iter()
+            .map(|(n, e)| format!("{}\t{}\t{}\t{}\t{}\n", n, e.length, e.offset, e.line_bases, e.line_width))
+            .collect();
+        assert_eq!(fai, FAI);
+    }
+
+    #[test]
+    fn test_index_fasta_rejects_irregular_wrapping() {
+        let err = index_fasta(&b">chr1\nACGT\nAC\nACGT\n"[..], "g.fa").unwrap_err();
+        assert!(err.to_string().contains("irregular line length in sequence 'chr1'"));
+        assert!(index_fasta(&b">chr1\nACGT\nACGTA\n"[..], "g.fa").is_err());
+        assert!(index_fasta(&b">chr1\nACGT\nAC"[..], "g.fa").is_ok());
+    }
+
+    #[test]
+    fn test_ensure_index_for_bgzf() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("g.fa.gz");
+        let (compressed, gzi) = bgzf(FASTA, 7);
+        std::fs::write(&path, compressed).unwrap();
+        ensure_index(path.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("g.fa.gz.fai")).unwrap(), FAI);
+        assert_eq!(std::fs::read(dir.path().join("g.fa.gz.gzi")).unwrap(), gzi);
+    }
+
+    #[test]
+    fn test_build_gzi_rejects_truncated_and_invalid_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("g.fa.gz");
+        let (compressed, _) = bgzf(FASTA, 7);
+        for cut in [10, 30] {
+            std::fs::write(&path, &compressed[..cut]).unwrap();
+            let err = build_gzi(path.to_str().unwrap()).unwrap_err();
+            assert!(err.to_string().contains("truncated BGZF block at offset 0"), "{}", err);
+        }
+
+        // A block size too small to hold its own header
+        let mut invalid = compressed.clone();
+        invalid[16..18].copy_from_slice(&10u16.to_le_bytes());
+        std::fs::write(&path, invalid).unwrap();
+        assert!(build_gzi(path.to_str().unwrap()).unwrap_err().to_string().contains("invalid BGZF block"));
+    }
+}
@@ -1,96 +1,394 @@
-use crate::error::Error;
-use crate::structures::{Strand, TranscriptRegion};
+use crate::error::{Code, Error};
+use crate::duplicates::{suffixed, FeatureIds};
+use crate::parallel::map_in_order;
+use crate::spans::{Span, SpanIndex};
+use crate::structures::{Annotation, Strand, TranscriptRegion};
 use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
 use std::fs::File;
 use std::io::Write;
 use std::io::{BufRead, BufReader, BufWriter};
 
 pub fn parse_gff3_to_regions(
     gff3_path: &str,
-    feature_types: &Vec<String>,
+    feature_types: &[String],
     errors: &mut Vec<Error>,
 ) -> anyhow::Result<Vec<TranscriptRegion>> {
+    Ok(parse_gff3(gff3_path, feature_types, errors)?.regions)
+}
+
+/// Parse GFF3 into regions of the requested features. CDS lines are also collected
+/// separately, even when not requested, so that coding spans can be annotated.
+pub fn parse_gff3(
+    gff3_path: &str,
+    feature_types: &[String],
+    errors: &mut Vec<Error>,
+) -> anyhow::Result<Annotation> {
+    parse_gff3_parallel(gff3_path, feature_types, errors, 1, false)
+}
+
+/// Lines given to each thread per batch in `parse_gff3_parallel`.
+const LINES_PER_THREAD: usize = 16 * 1024;
+
+/// The only attributes the parser needs; other attributes are skipped without allocating.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Attributes<'a> {
+    pub id: Option<&'a str>,
+    pub parent: Option<&'a str>,
+    pub name: Option<&'a str>,
+    pub biotype: Option<&'a str>, // biotype, gene_biotype, transcript_biotype, gene_type or transcript_type
+}
+
+impl<'a> Attributes<'a> {
+    pub(crate) fn parse(attr_str: &'a str) -> Self {
+        let mut attributes = Attributes::default();
+        for attr in attr_str.split(';') {
+            let Some((key, value)) = attr.split_once('=') else {
+                continue;
+            };
+            let value = Some(value.trim());
+            match key.trim() {
+                "ID" => attributes.id = value,
+                "Parent" => attributes.parent = value,
+                "Name" => attributes.name = value,
+                "biotype" | "gene_biotype" | "transcript_biotype" | "gene_type" | "transcript_type" => {
+                    attributes.biotype = value
+                }
+                _ => (),
+            }
+        }
+        attributes
+    }
+}
+
+/// Columns of a feature line, borrowed from the line and parsed without looking at the
+/// other lines.
+pub(crate) struct Record<'a> {
+    pub chromosome: &'a str,
+    pub feature_type: &'a str,
+    pub start: usize,
+    pub end: usize,
+    pub strand_char: char,
+    pub phase: Option<u8>,
+    pub attributes: Attributes<'a>,
+    /// 1-based line number in the GFF3.
+    pub line: usize,
+}
+
+/// Parse the columns of a line, or None for comments, malformed lines and features that
+/// are not used. Without a feature set, features of every type are kept.
+fn parse_record<'a>(line: &'a str, line_no: usize, feature_set: Option<&HashSet<&str>>) -> anyhow::Result<Option<Record<'a>>> {
+    if line.starts_with('#') || line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    let mut cols = [""; 9];
+    let mut count = 0;
+    for col in line.split('\t') {
+        if count == 9 {
+            return Ok(None);
+        }
+        cols[count] = col;
+        count += 1;
+    }
+    if count != 9 {
+        return Ok(None);
+    }
+
+    let feature_type = cols[2];
+    let start = cols[3].parse::<usize>()?;
+    let end = cols[4].parse::<usize>()?;
+    if let Some(feature_set) = feature_set
+        && !matches!(feature_type, "gene" | "mRNA" | "transcript" | "exon" | "CDS")
+        && !feature_set.contains(feature_type)
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(Record {
+        chromosome: cols[0],
+        feature_type,
+        start,
+        end,
+        strand_char: cols[6].chars().next().unwrap_or('.'),
+        phase: cols[7].parse::<u8>().ok().filter(|&p| p < 3),
+        attributes: Attributes::parse(cols[8]),
+        line: line_no,
+    }))
+}
+
+/// Shares one allocation between all copies of a string, such as seqids and parent IDs.
+#[derive(Default)]
+pub(crate) struct Interner {
+    strings: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&mut self, s: &str) -> Arc<str> {
+        if let Some(interned) = self.strings.get(s) {
+            return interned.clone();
+        }
+        let interned: Arc<str> = Arc::from(s);
+        self.strings.insert(interned.clone());
+        interned
+    }
+}
+
+/// `parse_gff3` with the columns of each batch of lines parsed on `threads` threads.
+/// Features are then linked to their parents in file order, so the result and the
+/// reported problems do not depend on the number of threads. With `rename_duplicates`,
+/// reused IDs are made unique instead of being reported as fatal.
+pub fn parse_gff3_parallel(
+    gff3_path: &str,
+    feature_types: &[String],
+    errors: &mut Vec<Error>,
+    threads: usize,
+    rename_duplicates: bool,
+) -> anyhow::Result<Annotation> {
     let feature_set: HashSet<&str> = feature_types.iter().map(|s| s.as_str()).collect();
-    let reader = BufReader::new(File::open(gff3_path)?);
-    let mut regions = Vec::new();
+    let mut linker = Linker::new(gff3_path, feature_types, rename_duplicates);
+    read_records(gff3_path, Some(&feature_set), threads, |record| {
+        linker.add(record, errors);
+        Ok(())
+    })?;
+    Ok(linker.finish(errors))
+}
 
-    let mut transcript_to_gene: HashMap<String, String> = HashMap::new();
-    let mut warn_missing_tx_parent = false;
-    let mut warn_missing_feature_parent = false;
+/// Read the feature lines of a GFF3 and pass them to `visit` in file order, parsing the
+/// columns of each batch of lines on `threads` threads. Lines are read into one reused
+/// buffer. Without a feature set, features of every type are visited.
+pub(crate) fn read_records(
+    gff3_path: &str,
+    feature_set: Option<&HashSet<&str>>,
+    threads: usize,
+    mut visit: impl FnMut(Record) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(File::open(gff3_path)?);
+    let mut buffer = String::new();
+    let mut lines: Vec<(usize, Range<usize>)> = Vec::new();
+    let mut line_no = 0;
 
-    for line in reader.lines() {
-        let line = line?;
-        if line.starts_with('#') || line.trim().is_empty() {
-            continue;
+    loop {
+        buffer.clear();
+        lines.clear();
+        while lines.len() < threads.max(1) * LINES_PER_THREAD {
+            let start = buffer.len();
+            if reader.read_line(&mut buffer)? == 0 {
+                break;
+            }
+            let line = buffer[start..].trim_end_matches(['\n', '\r']);
+            line_no += 1;
+            lines.push((line_no, start..start + line.len()));
+        }
+        if lines.is_empty() {
+            return Ok(());
         }
 
-        let cols: Vec<&str> = line.split('\t').collect();
-        if cols.len() != 9 {
-            continue;
+        let records = map_in_order(&lines, threads, |(line_no, line)| {
+            parse_record(&buffer[line.clone()], *line_no, feature_set)
+                .map_err(|e| anyhow::anyhow!("{}:{}: {}", gff3_path, line_no, e))
+        });
+        for record in records {
+            if let Some(record) = record? {
+                visit(record)?;
+            }
         }
+    }
+}
+
+/// Links features to their transcripts and genes, one line at a time in file order.
+/// Seqids and parent IDs are interned.
+pub(crate) struct Linker<'f> {
+    path: &'f str,
+    feature_set: HashSet<&'f str>,
+    regions: Vec<TranscriptRegion>,
+    cds: Vec<TranscriptRegion>,
+    gene_names: HashMap<String, String>,
+    biotypes: HashMap<String, String>,
+    interner: Interner,
+    transcript_to_gene: HashMap<Arc<str>, Arc<str>>,
+    spans: SpanIndex,
+    ids: FeatureIds,
+    warn_missing_tx_parent: bool,
+    warn_missing_feature_parent: bool,
+    unnamed: Vec<(usize, Arc<str>)>, // index in `regions` and type of the features without an ID
+    renamed: HashMap<usize, (Option<String>, Option<String>)>,
+    rejected: HashSet<Arc<str>>, // transcripts left out for a duplicate ID
+}
 
-        let chromosome = cols[0].to_owned();
-        let feature_type = cols[2];
-        let start = cols[3].parse::<usize>()?;
-        let end = cols[4].parse::<usize>()?;
-        let strand_char = cols[6].chars().next().unwrap_or('.');
+impl<'f> Linker<'f> {
+    pub(crate) fn new(path: &'f str, feature_types: &'f [String], rename_duplicates: bool) -> Self {
+        Linker {
+            path,
+            feature_set: feature_types.iter().map(|s| s.as_str()).collect(),
+            regions: Vec::new(),
+            cds: Vec::new(),
+            gene_names: HashMap::new(),
+            biotypes: HashMap::new(),
+            interner: Interner::default(),
+            transcript_to_gene: HashMap::new(),
+            spans: SpanIndex::default(),
+            ids: FeatureIds::new(rename_duplicates),
+            warn_missing_tx_parent: false,
+            warn_missing_feature_parent: false,
+            unnamed: Vec::new(),
+            renamed: HashMap::new(),
+            rejected: HashSet::new(),
+        }
+    }
 
-        let attributes = parse_attributes(cols[8]);
+    pub(crate) fn add(&mut self, record: Record, errors: &mut Vec<Error>) {
+        let Record { chromosome, feature_type, start, end, strand_char, phase, attributes, line } = record;
+        let parent = attributes.parent.map(|p| self.ids.parent(self.interner.intern(p)));
+        let id = match attributes.id {
+            Some(id) => {
+                let (id, kind) = (self.interner.intern(id), self.interner.intern(feature_type));
+                let Some(id) = self.ids.check(id.clone(), kind, parent.clone(), (self.path, line), errors) else {
+                    self.reject_duplicate(feature_type, id, parent, errors);
+                    return;
+                };
+                Some(id)
+            }
+            None => None,
+        };
+        let changed = |new: &Option<Arc<str>>, old: Option<&str>| new.as_deref().filter(|&n| Some(n) != old).map(str::to_string);
+        let (new_id, new_parent) = (changed(&id, attributes.id), changed(&parent, attributes.parent));
+        if new_id.is_some() || new_parent.is_some() {
+            self.renamed.insert(line, (new_id, new_parent));
+        }
+        let attributes = Attributes { id: id.as_deref(), parent: parent.as_deref(), ..attributes };
+        let span = |linker: &mut Self, kind, parent: Option<&str>| Span {
+            kind,
+            chromosome: linker.interner.intern(chromosome),
+            start,
+            end,
+            strand: Strand::from_char(strand_char),
+            parent: parent.map(|p| linker.interner.intern(p)),
+            line,
+        };
+        if feature_type == "exon"
+            && let Some(parent) = attributes.parent
+        {
+            let parent = self.interner.intern(parent);
+            self.spans.add_exon(parent, start, end);
+        }
 
         match feature_type {
             "gene" => {
-                if let Some(gene_id) = attributes.get("ID") {
-                    transcript_to_gene.insert(gene_id.clone(), gene_id.clone());
+                if let Some(gene_id) = attributes.id {
+                    let gene_id = self.interner.intern(gene_id);
+                    let span = span(self, "gene", None);
+                    self.spans.add(gene_id.clone(), span);
+                    self.transcript_to_gene.insert(gene_id.clone(), gene_id.clone());
+                    if let Some(name) = attributes.name {
+                        self.gene_names.insert(gene_id.to_string(), name.to_string());
+                    }
+                    if let Some(biotype) = attributes.biotype {
+                        self.biotypes.insert(gene_id.to_string(), biotype.to_string());
+                    }
                 }
             }
             "mRNA" | "transcript" => {
-                if let Some(transcript_id) = attributes.get("ID") {
-                    let gene_id = if let Some(parent) = attributes.get("Parent") {
-                        parent.clone()
+                if let Some(transcript_id) = attributes.id {
+                    let gene_id = if let Some(parent) = attributes.parent {
+                        self.interner.intern(parent)
                     } else {
-                        if !warn_missing_tx_parent {
-                            errors.push(Error::warning(
-                                "Transcript entry missing Parent attribute; using transcript ID as gene ID",
-                            ));
-                            warn_missing_tx_parent = true;
+                        if !self.warn_missing_tx_parent {
+                            errors.push(
+                                Error::warning(
+                                    Code::MissingTranscriptParent,
+                                    "Transcript entry missing Parent attribute; using transcript ID as gene ID",
+                                )
+                                .for_transcript(transcript_id)
+                                .at(self.path, line),
+                            );
+                            self.warn_missing_tx_parent = true;
                         }
-                        transcript_id.clone()
+                        self.interner.intern(transcript_id)
                     };
-                    transcript_to_gene.insert(transcript_id.clone(), gene_id);
+                    // Without its own biotype, a transcript takes that of its gene, or its type
+                    let biotype = attributes
+                        .biotype
+                        .or_else(|| self.biotypes.get(&*gene_id).map(|b| b.as_str()))
+                        .unwrap_or(feature_type);
+                    self.biotypes.insert(transcript_id.to_string(), biotype.to_string());
+                    let span = span(self, "transcript", attributes.parent);
+                    self.spans.add(self.interner.intern(transcript_id), span);
+                    self.transcript_to_gene.insert(self.interner.intern(transcript_id), gene_id);
                 }
             }
-            feat if feature_set.contains(feat) => {
-                let region_id = if let Some(id) = attributes.get("ID") {
-                    id.clone()
-                } else {
-                    errors.push(Error::fatal("Missing transcript id".to_string()));
-                    continue;
+            feat if self.feature_set.contains(feat) => {
+                // Features without an ID are named once all lines are read; without a
+                // Parent either, they cannot be attached to anything.
+                let Some(region_id) = attributes.id.or(attributes.parent.map(|_| "")) else {
+                    errors.push(
+                        Error::fatal(
+                            Code::MissingFeatureId,
+                            format!("{} feature has neither ID nor Parent and cannot be attached to a transcript", feature_type),
+                        )
+                        .at(self.path, line),
+                    );
+                    return;
                 };
 
-                let transcript_id = if let Some(parent) = attributes.get("Parent") {
-                    parent.clone()
+                let transcript_id = if let Some(parent) = attributes.parent {
+                    self.interner.intern(parent)
                 } else {
-                    if !warn_missing_feature_parent {
-                        errors.push(Error::warning(
-                            "Feature missing Parent attribute; using feature ID as transcript and gene ID",
-                        ));
-                        warn_missing_feature_parent = true;
+                    if !self.warn_missing_feature_parent {
+                        errors.push(
+                            Error::warning(
+                                Code::MissingFeatureParent,
+                                "Feature missing Parent attribute; using feature ID as transcript and gene ID",
+                            )
+                            .for_feature(region_id)
+                            .at(self.path, line),
+                        );
+                        self.warn_missing_feature_parent = true;
                     }
-                    transcript_to_gene.insert(region_id.clone(), region_id.clone());
-                    region_id.clone()
+                    let id = self.interner.intern(region_id);
+                    self.transcript_to_gene.insert(id.clone(), id.clone());
+                    id
                 };
 
-                let gene_id = transcript_to_gene.get(&transcript_id).cloned();
+                let gene_id = self.transcript_to_gene.get(&transcript_id).cloned();
 
-                if let Some(strand) = Strand::from_char(strand_char, errors) {
-                    regions.push(TranscriptRegion {
-                        chromosome,
+                if let Some(strand) = self.strand(strand_char, line, errors) {
+                    let region = TranscriptRegion {
+                        chromosome: self.interner.intern(chromosome),
                         start,
                         end,
-                        region_id,
+                        region_id: region_id.to_string(),
                         strand,
                         transcript_id,
                         gene_id,
+                        phase,
+                        line,
+                    };
+                    if feature_type == "CDS" {
+                        self.cds.push(region.clone());
+                    }
+                    if attributes.id.is_none() {
+                        self.unnamed.push((self.regions.len(), self.interner.intern(feature_type)));
+                    }
+                    self.regions.push(region);
+                }
+            }
+            "CDS" => {
+                // Not extracted, only used for the coding span; IDs are optional here.
+                if let Some(transcript_id) = attributes.parent
+                    && let Some(strand) = self.strand(strand_char, line, errors)
+                {
+                    self.cds.push(TranscriptRegion {
+                        chromosome: self.interner.intern(chromosome),
+                        start,
+                        end,
+                        region_id: attributes.id.unwrap_or_default().to_string(),
+                        strand,
+                        transcript_id: self.interner.intern(transcript_id),
+                        gene_id: self.transcript_to_gene.get(transcript_id).cloned(),
+                        phase,
+                        line,
                     });
                 }
             }
@@ -98,7 +396,107 @@ pub fn parse_gff3_to_regions(
         }
     }
 
-    Ok(regions)
+    /// Leave out the transcript that a line with a duplicate ID belongs to, whatever the
+    /// severity the policy gives the problem: the transcript itself, or the parent of a
+    /// feature. The problem just reported is attached to it.
+    fn reject_duplicate(&mut self, feature_type: &str, id: Arc<str>, parent: Option<Arc<str>>, errors: &mut [Error]) {
+        let owner = match feature_type {
+            "mRNA" | "transcript" => Some(id),
+            "CDS" => parent,
+            feat if self.feature_set.contains(feat) => parent,
+            _ => None,
+        };
+        if let Some(owner) = owner {
+            if let Some(error) = errors.last_mut() {
+                error.transcript_id = Some(owner.to_string());
+            }
+            self.rejected.insert(owner);
+        }
+    }
+
+    /// The strand of a feature line, reporting values other than `+` and `-`.
+    fn strand(&self, strand_char: char, line: usize, errors: &mut Vec<Error>) -> Option<Strand> {
+        let strand = Strand::from_char(strand_char);
+        if strand.is_none() {
+            errors.push(Error::fatal(Code::InvalidStrand, format!("Invalid strand [{strand_char}]")).at(self.path, line));
+        }
+        strand
+    }
+
+    /// Give each feature without an ID one made of its transcript, its type and its rank in
+    /// transcript order among the unnamed features of that type, such as `tx1.exon2`, and
+    /// report them all in one warning. A name already used by a line of the file is suffixed
+    /// as a renamed duplicate would be, such as `tx1.exon2_dup2`.
+    fn name_unnamed(&mut self, errors: &mut Vec<Error>) {
+        let Some(&(first, _)) = self.unnamed.first() else {
+            return;
+        };
+        // Groups in file order, so that the suffixes do not depend on hashing
+        let mut groups: Vec<(Arc<str>, Arc<str>, Vec<usize>)> = Vec::new();
+        let mut group_of: HashMap<(Arc<str>, Arc<str>), usize> = HashMap::new();
+        for (i, feature_type) in &self.unnamed {
+            let transcript_id = self.regions[*i].transcript_id.clone();
+            let group = *group_of.entry((transcript_id.clone(), feature_type.clone())).or_insert_with(|| {
+                groups.push((transcript_id, feature_type.clone(), Vec::new()));
+                groups.len() - 1
+            });
+            groups[group].2.push(*i);
+        }
+        let mut synthesized: HashSet<String> = HashSet::new();
+        for (transcript_id, feature_type, mut indexes) in groups {
+            match self.regions[indexes[0]].strand {
+                Strand::Plus => indexes.sort_by_key(|&i| self.regions[i].start),
+                Strand::Minus => indexes.sort_by_key(|&i| std::cmp::Reverse(self.regions[i].start)),
+            }
+            for (n, i) in indexes.into_iter().enumerate() {
+                let base = format!("{}.{}{}", transcript_id, feature_type, n + 1);
+                let mut name = base.clone();
+                let mut copy = 1;
+                while self.ids.contains(&name) || synthesized.contains(&name) {
+                    copy += 1;
+                    name = suffixed(&base, copy);
+                }
+                synthesized.insert(name.clone());
+                self.renamed.entry(self.regions[i].line).or_default().0 = Some(name.clone());
+                self.regions[i].region_id = name;
+            }
+        }
+        let example = &self.regions[first];
+        errors.push(
+            Error::warning(
+                Code::SynthesizedId,
+                format!(
+                    "{} feature(s) without an ID were named after their transcript, such as {}",
+                    self.unnamed.len(),
+                    example.region_id
+                ),
+            )
+            .at(self.path, example.line),
+        );
+    }
+
+    /// The linked annotation, after checking the features against the spans of their
+    /// transcripts and genes. Transcripts rejected for a duplicate ID are left out.
+    pub(crate) fn finish(mut self, errors: &mut Vec<Error>) -> Annotation {
+        self.name_unnamed(errors);
+        let rejected = &self.rejected;
+        self.regions.retain(|r| !rejected.contains(&r.transcript_id));
+        self.cds.retain(|r| !rejected.contains(&r.transcript_id));
+        let mut features: Vec<&TranscriptRegion> = self.regions.iter().collect();
+        if !self.feature_set.contains("CDS") {
+            features.extend(&self.cds);
+        }
+        features.sort_by_key(|f| f.line);
+        self.spans.check(&features, self.path, errors);
+        Annotation {
+            regions: self.regions,
+            cds: self.cds,
+            gene_names: self.gene_names,
+            biotypes: self.biotypes,
+            source: self.path.to_string(),
+            renamed: self.renamed,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -136,24 +534,14 @@ pub fn write_genemap(regions: &[TranscriptRegion], out_path: &str) -> anyhow::Re
     Ok(())
 }
 
-fn parse_attributes(attr_str: &str) -> HashMap<String, String> {
-    attr_str
-        .trim_end_matches(';')
-        .split(';')
-        .filter_map(|attr| attr.split_once('='))
-        .map(|(k, v)| (k.trim().to_owned(), v.trim().to_owned()))
-        .collect()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::error::Severity;
     #[test]
     fn test_parse_attributes_basic() {
-        let attrs = parse_attributes("ID=exon1;Parent=tx1;");
-        assert_eq!(attrs.get("ID"), Some(&"exon1".to_string()));
-        assert_eq!(attrs.get("Parent"), Some(&"tx1".to_string()));
+        let attrs = Attributes::parse("ID=exon1;Parent=tx1;Note=a b;");
+        assert_eq!(attrs, Attributes { id: Some("exon1"), parent: Some("tx1"), name: None, biotype: None });
     }
 
     #[test]
@@ -166,10 +554,10 @@ mod tests {
         writeln!(file, "chr1	src	exon	6	10	.	+	.	ID=ex2;Parent=tx1;").unwrap();
         let path = file.path().to_str().unwrap().to_string();
         let mut errors = Vec::new();
-        let regions = parse_gff3_to_regions(&path, &vec!["exon".to_string()], &mut errors).unwrap();
+        let regions = parse_gff3_to_regions(&path, &["exon".to_string()], &mut errors).unwrap();
         assert!(errors.is_empty());
         assert_eq!(regions.len(), 2);
-        assert_eq!(regions[0].transcript_id, "tx1");
+        assert_eq!(&*regions[0].transcript_id, "tx1");
     }
 
     #[test]
@@ -181,14 +569,62 @@ mod tests {
         writeln!(file, "chr1\tsrc\texon\t6\t10\t.\t+\t.\tID=ex2").unwrap();
         let path = file.path().to_str().unwrap().to_string();
         let mut errors = Vec::new();
-        let regions = parse_gff3_to_regions(&path, &vec!["exon".to_string()], &mut errors).unwrap();
+        let regions = parse_gff3_to_regions(&path, &["exon".to_string()], &mut errors).unwrap();
         assert_eq!(regions.len(), 2);
         assert!(
             errors
                 .iter()
                 .any(|e| matches!(e.severity, Severity::Warning))
         );
-        assert_eq!(regions[0].transcript_id, "tx1");
-        assert_eq!(regions[1].transcript_id, "ex2");
+        assert_eq!(&*regions[0].transcript_id, "tx1");
+        assert_eq!(&*regions[1].transcript_id, "ex2");
+    }
+
+    #[test]
+    fn test_problems_carry_code_ids_and_location() {
+        use crate::transcript_builder::build_transcripts;
+        use std::io::Write;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "##gff-version 3").unwrap();
+        writeln!(file, "chr1\tsrc\tmRNA\t1\t10\t.\t+\t.\tID=tx1;Parent=g1").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t1\t5\t.\t+\t.\tID=ex1;Parent=tx1").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t6\t10\t.\t-\t.\tID=ex2;Parent=tx1").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t6\t10\t.\t?\t.\tID=ex3;Parent=tx1").unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let mut errors = Vec::new();
+        let annotation = parse_gff3(&path, &["exon".to_string()], &mut errors).unwrap();
+        build_transcripts(annotation, &mut errors);
+
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0].code, Code::InvalidStrand);
+        assert_eq!((errors[0].file.as_deref(), errors[0].line), (Some(path.as_str()), Some(5)));
+        assert_eq!(errors[1].code, Code::ParentMismatch);
+        assert_eq!((errors[1].feature_id.as_deref(), errors[1].line), (Some("ex2"), Some(4)));
+        assert_eq!(errors[2].code, Code::MixedStrand);
+        assert_eq!(errors[2].transcript_id.as_deref(), Some("tx1"));
+        assert_eq!(errors[2].gene_id.as_deref(), Some("g1"));
+        assert_eq!((errors[2].file.as_deref(), errors[2].line), (Some(path.as_str()), Some(3)));
+    }
+
+    #[test]
+    fn test_features_without_id_are_named_in_transcript_order() {
+        use std::io::Write;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "chr1\tsrc\tmRNA\t1\t60\t.\t-\t.\tID=tx1;Parent=g1").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t1\t10\t.\t-\t.\tParent=tx1").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t50\t60\t.\t-\t.\tParent=tx1").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t20\t30\t.\t-\t.\tParent=tx1").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t70\t80\t.\t-\t.\tNote=orphan").unwrap();
+        writeln!(file, "chr1\tsrc\texon\t100\t110\t.\t+\t.\tID=tx1.exon1;Parent=tx2").unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let mut errors = Vec::new();
+        let regions = parse_gff3_to_regions(&path, &["exon".to_string()], &mut errors).unwrap();
+        let ids: Vec<&str> = regions.iter().map(|r| r.region_id.as_str()).collect();
+        // The name of the first exon is taken by a line of the file
+        assert_eq!(ids, ["tx1.exon3", "tx1.exon1_dup2", "tx1.exon2", "tx1.exon1"]);
+
+        let codes: Vec<(Code, Option<usize>)> = errors.iter().map(|e| (e.code, e.line)).collect();
+        assert_eq!(codes, [(Code::MissingFeatureId, Some(5)), (Code::SynthesizedId, Some(2))]);
+        assert!(errors[1].message.starts_with("3 feature(s) without an ID"));
     }
 }
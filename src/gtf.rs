@@ -0,0 +1,110 @@
+use crate::structures::{Strand, Transcript};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Write transcripts as GTF: a transcript line, its exons and, for coding transcripts,
+/// CDS with start_codon and stop_codon. The CDS span is assumed to include the stop codon,
+/// as in GFF3; the GTF CDS lines exclude it.
+pub fn write_gtf(transcripts: &[Transcript], out_path: &str) -> anyhow::Result<()> {
+    let mut writer = BufWriter::new(File::create(out_path)?);
+    for transcript in transcripts {
+        for line in gtf_lines(transcript) {
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Format all GTF lines of a single transcript.
+pub fn gtf_lines(transcript: &Transcript) -> Vec<String> {
+    let gene_id = transcript.gene_id.as_deref().unwrap_or(&transcript.id);
+    let mut ids = format!("gene_id \"{}\"; transcript_id \"{}\";", gene_id, transcript.id);
+    if let Some(name) = &transcript.gene_name {
+        ids.push_str(&format!(" gene_name \"{}\";", name));
+    }
+    let line = |feature: &str, start: usize, end: usize, phase: &str, attributes: &str| {
+        format!(
+            "{}\tthaf\t{}\t{}\t{}\t.\t{}\t{}\t{}",
+            transcript.chromosome,
+            feature,
+            start,
+            end,
+            transcript.strand(),
+            phase,
+            attributes
+        )
+    };
+
+    let mut lines = vec![line("transcript", transcript.start(), transcript.end(), ".", &ids)];
+
+    for (index, region) in transcript.regions.iter().enumerate() {
+        let attributes = format!("{} exon_number \"{}\";", ids, index + 1);
+        lines.push(line("exon", region.start, region.end, ".", &attributes));
+    }
+
+    if let Some((from, to)) = transcript.cds_offsets() {
+        // Too short to hold both codons: report the CDS as is
+        let (cds_to, codons) = if to - from >= 6 { (to - 3, true) } else { (to, false) };
+
+        for (index, start, end) in transcript.genomic_segments(from, cds_to) {
+            let five_prime = transcript.transcript_offset(match transcript.strand() {
+                Strand::Plus => start,
+                Strand::Minus => end,
+            });
+            let phase = five_prime.map_or(0, |offset| (3 - (offset - from) % 3) % 3);
+            let attributes = format!("{} exon_number \"{}\";", ids, index + 1);
+            lines.push(line("CDS", start, end, &phase.to_string(), &attributes));
+        }
+
+        if codons {
+            for (feature, a, b) in [("start_codon", from, from + 3), ("stop_codon", to - 3, to)] {
+                let mut done = 0;
+                for (index, start, end) in transcript.genomic_segments(a, b) {
+                    let attributes = format!("{} exon_number \"{}\";", ids, index + 1);
+                    lines.push(line(feature, start, end, &((3 - done % 3) % 3).to_string(), &attributes));
+                    done += end - start + 1;
+                }
+            }
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gtf_minus_strand_coding() {
+        // Exons 301..320 and 101..120 on the minus strand, CDS 111..315 including stop codon
+        let t = Transcript::test("tx1", "chr1", Strand::Minus, &[(301, 320), (101, 120)])
+            .gene("g1", None)
+            .coding(Some((111, 315)));
+        let lines = gtf_lines(&t);
+        let ids = "gene_id \"g1\"; transcript_id \"tx1\";";
+        assert_eq!(lines, vec![
+            format!("chr1\tthaf\ttranscript\t101\t320\t.\t-\t.\t{ids}"),
+            format!("chr1\tthaf\texon\t301\t320\t.\t-\t.\t{ids} exon_number \"1\";"),
+            format!("chr1\tthaf\texon\t101\t120\t.\t-\t.\t{ids} exon_number \"2\";"),
+            format!("chr1\tthaf\tCDS\t301\t315\t.\t-\t0\t{ids} exon_number \"1\";"),
+            format!("chr1\tthaf\tCDS\t114\t120\t.\t-\t0\t{ids} exon_number \"2\";"),
+            format!("chr1\tthaf\tstart_codon\t313\t315\t.\t-\t0\t{ids} exon_number \"1\";"),
+            format!("chr1\tthaf\tstop_codon\t111\t113\t.\t-\t0\t{ids} exon_number \"2\";"),
+        ]);
+    }
+
+    #[test]
+    fn test_gtf_split_codon_phase() {
+        // Start codon split 2+1 across the junction
+        let t = Transcript::test("tx1", "chr1", Strand::Plus, &[(1, 10), (21, 40)]).coding(Some((9, 30)));
+        let lines = gtf_lines(&t);
+        assert!(lines[3].contains("\tCDS\t9\t10\t.\t+\t0\t"));
+        assert!(lines[4].contains("\tCDS\t21\t27\t.\t+\t1\t"));
+        assert!(lines[5].contains("\tstart_codon\t9\t10\t.\t+\t0\t"));
+        assert!(lines[6].contains("\tstart_codon\t21\t21\t.\t+\t1\t"));
+        assert!(lines[7].contains("\tstop_codon\t28\t30\t.\t+\t0\t"));
+        assert!(lines[0].contains("gene_id \"tx1\"; transcript_id \"tx1\";"));
+    }
+}
@@ -3,3 +3,23 @@ pub mod structures;
 pub mod gff3;
 pub mod error;
 
+pub mod bed;
+pub mod gtf;
+pub mod genepred;
+pub mod normalize;
+pub mod coordinates;
+pub mod variants;
+pub mod features;
+pub mod genome;
+pub mod streaming;
+pub mod packed;
+pub mod twobit;
+pub mod parallel;
+pub mod cache;
+pub mod batch;
+pub mod policy;
+pub mod output;
+pub mod stats;
+pub mod qc;
+pub mod spans;
+pub mod duplicates;
@@ -1,13 +1,26 @@
-mod transcript_builder;
-mod structures;
-mod gff3;
-mod error;
-
-use crate::gff3::{parse_gff3_to_regions, write_genemap};
-use crate::transcript_builder::{build_transcriptome_sequences, build_transcripts_from_regions};
-use crate::error::{Error, Severity};
+use thaf::batch::{read_manifest, run_batch, EntryOptions};
+use thaf::bed::write_bed12;
+use thaf::cache::{parse_gff3_cached, CacheStatus};
+use thaf::coordinates::{map_bed, map_vcf, Direction};
+use thaf::features::{write_feature_sequences, write_unique_feature_sequences};
+use thaf::genepred::{write_genepred, write_refflat};
+use thaf::gff3::{parse_gff3, parse_gff3_parallel, write_genemap};
+use thaf::parallel::resolve_threads;
+use thaf::gtf::write_gtf;
+use thaf::normalize::write_normalized_gff3;
+use thaf::genome::{build_index, ensure_index, open_genome, open_genome_with, reads_on_demand};
+use thaf::streaming::{needs_streaming, open_fasta_stream, stream_transcriptome, OutputOrder};
+use thaf::transcript_builder::{build_transcripts, write_transcriptome};
+use thaf::variants::{load_variants, write_personal_transcriptome};
+use thaf::error::{write_report, Error, ReportFormat, Severity};
+use thaf::output::StagedOutputs;
+use thaf::policy::Policy;
+use thaf::qc::{write_qc_table, CdsLines};
+use thaf::stats::{write_stats, AnnotationFacts, Stats};
+use thaf::structures::Transcript;
 use anyhow::Result;
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::io::Write;
 
 fn main() -> Result<()> {
     let matches = Command::new("thaf")
@@ -53,6 +66,163 @@ fn main() -> Result<()> {
                 .help("Features to extract (comma-separated, defaults to 'exon')")
                 .required(false),
         )
+        .arg(
+            Arg::new("bed")
+                .short('b')
+                .long("bed")
+                .value_name("BED12_FILE")
+                .help("Output BED12 file with the extracted transcript models")
+                .required(false),
+        )
+        .arg(
+            Arg::new("gtf")
+                .long("gtf")
+                .value_name("GTF_FILE")
+                .help("Output GTF file with the extracted transcript models")
+                .required(false),
+        )
+        .arg(
+            Arg::new("genepred")
+                .long("genepred")
+                .value_name("GENEPRED_FILE")
+                .help("Output genePred file with the extracted transcript models")
+                .required(false),
+        )
+        .arg(
+            Arg::new("refflat")
+                .long("refflat")
+                .value_name("REFFLAT_FILE")
+                .help("Output refFlat file with the extracted transcript models")
+                .required(false),
+        )
+        .arg(
+            Arg::new("normalized")
+                .long("normalized-gff3")
+                .value_name("GFF3_FILE")
+                .help("Output sorted and cleaned GFF3, with rejected transcripts commented out")
+                .required(false),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .value_name("STATS_TXT")
+                .help("Output statistics of the extracted and rejected transcripts as text"),
+        )
+        .arg(
+            Arg::new("stats_json")
+                .long("stats-json")
+                .value_name("STATS_JSON")
+                .help("Output the statistics as JSON"),
+        )
+        .arg(
+            Arg::new("stats_html")
+                .long("stats-html")
+                .value_name("STATS_HTML")
+                .help("Output the statistics as a self-contained HTML page"),
+        )
+        .arg(
+            Arg::new("vcf")
+                .long("vcf")
+                .value_name("VCF")
+                .help("Apply SNVs and indels from this VCF to the extracted transcripts")
+                .required(false),
+        )
+        .arg(
+            Arg::new("sample")
+                .long("sample")
+                .value_name("SAMPLE")
+                .help("Take the variants from the genotype of this VCF sample")
+                .requires("vcf")
+                .required(false),
+        )
+        .arg(
+            Arg::new("haplotype")
+                .long("haplotype")
+                .value_name("1|2")
+                .value_parser(["1", "2"])
+                .help("Use only the alleles of this haplotype of the sample")
+                .requires("sample")
+                .required(false),
+        )
+        .arg(
+            Arg::new("variant_table")
+                .long("variant-table")
+                .value_name("VARIANT_TSV")
+                .help("Output TSV listing the variants applied to each transcript")
+                .requires("vcf")
+                .required(false),
+        )
+        .arg(
+            Arg::new("feature_fasta")
+                .long("feature-fasta")
+                .value_name("FEATURE_FASTA")
+                .help("Output FASTA with every extracted feature as its own record")
+                .required(false),
+        )
+        .arg(
+            Arg::new("unique_features")
+                .long("unique-features")
+                .action(ArgAction::SetTrue)
+                .help("Write features shared by several transcripts only once")
+                .requires("feature_fasta"),
+        )
+        .arg(
+            Arg::new("feature_map")
+                .long("feature-map")
+                .value_name("FEATURE_TSV")
+                .help("Output TSV mapping each unique feature to its transcripts")
+                .requires("unique_features")
+                .required(false),
+        )
+        .arg(rename_duplicates_arg())
+        .arg(
+            Arg::new("qc")
+                .long("qc")
+                .value_name("QC_TSV")
+                .help("Check splice-site motifs and CDS codons and phases, and write one line per transcript")
+                .required(false),
+        )
+        .arg(
+            Arg::new("cache")
+                .long("cache")
+                .value_name("CACHE_FILE")
+                .help("Binary cache of the parsed GFF3, used if up to date and rebuilt otherwise")
+                .required(false),
+        )
+        .arg(
+            Arg::new("threads")
+                .long("threads")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Worker threads for parsing and extraction (0 for one per core)"),
+        )
+        .arg(
+            Arg::new("stream")
+                .long("stream")
+                .action(ArgAction::SetTrue)
+                .help("Read the genome one chromosome at a time instead of loading it or using its index"),
+        )
+        .arg(
+            Arg::new("order")
+                .long("order")
+                .value_name("annotation|genome")
+                .value_parser(["annotation", "genome"])
+                .default_value("annotation")
+                .help("Order of the transcript sequences when the genome is streamed"),
+        )
+        .arg(
+            Arg::new("packed")
+                .long("packed")
+                .action(ArgAction::SetTrue)
+                .help("Keep a genome loaded into memory packed, 2 bits per base"),
+        )
+        .arg(
+            Arg::new("build_index")
+                .long("build-index")
+                .action(ArgAction::SetTrue)
+                .help("Create the .fai (and .gzi) index of the genome if missing, and read it through them"),
+        )
         .arg(
             Arg::new("error")
                 .short('r')
@@ -61,65 +231,487 @@ fn main() -> Result<()> {
                 .help("Write warnings and errors to this file")
                 .required(false),
         )
+        .arg(error_format_arg())
+        .args(policy_args())
+        .arg(keep_partial_arg())
+        .subcommand(
+            Command::new("index")
+                .about("Create the samtools-compatible .fai (and .gzi for BGZF) index of a genome FASTA.")
+                .arg(
+                    Arg::new("dna")
+                        .short('d')
+                        .long("dna")
+                        .value_name("DNA_FASTA")
+                        .help("Genome FASTA file to index")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("batch")
+                .about("Extract transcriptomes for every genome/annotation pair of a manifest.")
+                .arg(
+                    Arg::new("manifest")
+                        .short('m')
+                        .long("manifest")
+                        .value_name("MANIFEST")
+                        .help("TSV (name, gff3, fasta, prefix) or TOML ([[entry]] tables) manifest")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("summary")
+                        .short('s')
+                        .long("summary")
+                        .value_name("SUMMARY_TSV")
+                        .help("Output TSV with one line per entry")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("features")
+                        .short('e')
+                        .long("features")
+                        .value_name("FEATURES")
+                        .help("Features to extract (comma-separated, defaults to 'exon')")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("jobs")
+                        .short('j')
+                        .long("jobs")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1")
+                        .help("Entries processed at the same time (each holds its genome in memory)"),
+                )
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1")
+                        .help("Worker threads for each entry (0 for one per core)"),
+                )
+                .arg(rename_duplicates_arg())
+                .args(policy_args())
+                .arg(keep_partial_arg()),
+        )
+        .subcommand(
+            Command::new("map")
+                .about("Convert BED or VCF between genomic and transcript coordinates.")
+                .arg(
+                    Arg::new("gff3")
+                        .short('f')
+                        .long("gff3")
+                        .value_name("INPUT_GFF3")
+                        .help("Input GFF3 annotation file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("features")
+                        .short('e')
+                        .long("features")
+                        .value_name("FEATURES")
+                        .help("Features forming the transcripts (comma-separated, defaults to 'exon')")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("dna")
+                        .short('d')
+                        .long("dna")
+                        .value_name("DNA_FASTA")
+                        .help("Genome FASTA, needed to re-anchor VCF indels on the minus strand")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("input")
+                        .short('i')
+                        .long("input")
+                        .value_name("INPUT")
+                        .help("Input BED or VCF file (VCF if the name ends with .vcf)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("OUTPUT")
+                        .help("Output file in the same format as the input")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("to_genome")
+                        .long("to-genome")
+                        .action(ArgAction::SetTrue)
+                        .help("Convert from transcript to genomic coordinates instead"),
+                )
+                .arg(
+                    Arg::new("error")
+                        .short('r')
+                        .long("error")
+                        .value_name("ERROR_LOG")
+                        .help("Write warnings and errors to this file")
+                        .required(false),
+                )
+                .arg(error_format_arg())
+                .args(policy_args())
+                .arg(keep_partial_arg()),
+        )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
         .arg_required_else_help(true)
         .get_matches();
 
+    match matches.subcommand() {
+        Some(("map", sub)) => return run_map(sub),
+        Some(("batch", sub)) => return run_batch_command(sub),
+        Some(("index", sub)) => return build_index(sub.get_one::<String>("dna").unwrap()),
+        _ => (),
+    }
+
     let input_file = matches.get_one::<String>("gff3").unwrap();
     let dna_fasta = matches.get_one::<String>("dna").unwrap();
     let transcriptome_fasta = matches.get_one::<String>("transcriptome").unwrap();
     let genemap_file = matches.get_one::<String>("genemap");
-    let error_file = matches.get_one::<String>("error");
-    let features: Vec<String> = matches
-        .get_one::<String>("features")
-        .map(|s| s.split(',').map(|item| item.trim().to_string()).collect())
-        .unwrap_or_else(|| vec!["exon".to_string()]);
+    let bed_file = matches.get_one::<String>("bed");
+    let gtf_file = matches.get_one::<String>("gtf");
+    let genepred_file = matches.get_one::<String>("genepred");
+    let refflat_file = matches.get_one::<String>("refflat");
+    let normalized_file = matches.get_one::<String>("normalized");
+    let features = parse_features(&matches);
+    let threads = resolve_threads(*matches.get_one::<usize>("threads").unwrap());
+    let policy = build_policy(&matches)?;
+
+    // Stream the genome where it cannot be indexed: from the standard input or plain gzip.
+    // Options that need random access are rejected before anything is read or written.
+    if matches.get_flag("build_index") {
+        ensure_index(dna_fasta)?;
+    }
+    let stream = matches.get_flag("stream") || needs_streaming(dna_fasta)?;
+    if stream {
+        if matches.contains_id("feature_fasta") {
+            anyhow::bail!("--feature-fasta needs random access to the genome and cannot be used with a streamed genome");
+        }
+        if matches.contains_id("qc") {
+            anyhow::bail!("--qc needs random access to the genome and cannot be used with a streamed genome");
+        }
+    }
+    let mut outputs = StagedOutputs::new(matches.get_flag("keep_partial"));
 
     let mut errors: Vec<Error> = Vec::new();
-    
+    let rename_duplicates = matches.get_flag("rename_duplicates");
+
     println!("  Features: {:?}", features);
 
     // Parsing regions from GFF3
-    let regions = parse_gff3_to_regions(input_file,
-                                        &features,
-                                        &mut errors)?;
-    let gene_count = regions
+    let mut annotation = match matches.get_one::<String>("cache") {
+        Some(cache) => {
+            let (annotation, status) = parse_gff3_cached(input_file, cache, &features, &mut errors, threads, rename_duplicates)?;
+            match status {
+                CacheStatus::Loaded => println!("  Cache: loaded {}", cache),
+                CacheStatus::Rebuilt => println!("  Cache: rebuilt {}", cache),
+            }
+            annotation
+        }
+        None => parse_gff3_parallel(input_file, &features, &mut errors, threads, rename_duplicates)?,
+    };
+    let facts = ["stats", "stats_json", "stats_html"]
         .iter()
-        .filter_map(|r| r.gene_id.clone())
-        .collect::<std::collections::HashSet<_>>()
-        .len();
+        .any(|id| matches.contains_id(id))
+        .then(|| AnnotationFacts::new(&annotation));
+    let cds_lines = matches.contains_id("qc").then(|| CdsLines::new(&annotation));
+    let renamed = std::mem::take(&mut annotation.renamed);
 
     // Optionally write genemap
     if let Some(genemap_path) = genemap_file {
-        write_genemap(&regions, genemap_path)?;
+        write_genemap(&annotation.regions, &outputs.stage(genemap_path))?;
     }
 
     // Build transcripts from regions
-    let transcripts = build_transcripts_from_regions(regions, &mut errors);
+    let transcripts = build_transcripts(annotation, &mut errors);
     let transcript_count = transcripts.len();
+    let gene_count = transcripts
+        .iter()
+        .map(|t| t.gene_id.as_ref().unwrap_or(&t.id))
+        .collect::<std::collections::HashSet<_>>()
+        .len();
+    // Stop after any stage that exceeds the warning limit; report_errors judges the final count
+    if policy.too_many_warnings(&errors) {
+        return report_errors(errors, &policy, outputs, &matches);
+    }
 
-    // Extract and write transcript sequences
-    build_transcriptome_sequences(&transcripts, dna_fasta, transcriptome_fasta)?;
+    // Optionally write transcript models, in the same order as the FASTA
+    if let Some(bed_path) = bed_file {
+        write_bed12(&transcripts, &outputs.stage(bed_path))?;
+    }
+    if let Some(gtf_path) = gtf_file {
+        write_gtf(&transcripts, &outputs.stage(gtf_path))?;
+    }
+    if let Some(genepred_path) = genepred_file {
+        write_genepred(&transcripts, &outputs.stage(genepred_path))?;
+    }
+    if let Some(refflat_path) = refflat_file {
+        write_refflat(&transcripts, &outputs.stage(refflat_path))?;
+    }
 
-    println!("Produced {} transcripts from {} genes", transcript_count, gene_count);
+    let variants = match matches.get_one::<String>("vcf") {
+        Some(vcf) => {
+            let sample = matches.get_one::<String>("sample").map(|s| s.as_str());
+            let haplotype = matches.get_one::<String>("haplotype").map(|h| h.parse::<usize>()).transpose()?;
+            let variants = load_variants(vcf, sample, haplotype, &mut errors)?;
+            println!("  Variants: {}", variants.len());
+            Some(variants)
+        }
+        None => None,
+    };
+    if policy.too_many_warnings(&errors) {
+        return report_errors(errors, &policy, outputs, &matches);
+    }
+    let table = matches.get_one::<String>("variant_table").map(|p| outputs.stage(p));
+    let table = table.as_deref();
+    let transcriptome_fasta = &outputs.stage(transcriptome_fasta);
 
-    if let Some(path) = error_file {
-        use std::fs::File;
-        use std::io::Write;
-        let mut f = File::create(path)?;
-        for e in &errors {
-            writeln!(f, "[{:?}] {}", e.severity, e.message)?;
+    if stream {
+        let order = match matches.get_one::<String>("order").map(|s| s.as_str()) {
+            Some("genome") => OutputOrder::Genome,
+            _ => OutputOrder::Annotation,
+        };
+        // Sequence lengths are not known before the genome has been read
+        if let Some(normalized_path) = normalized_file {
+            write_normalized_gff3(input_file, &transcripts, &errors, &renamed, None, &outputs.stage(normalized_path))?;
         }
+        let stream = open_fasta_stream(dna_fasta, rename_duplicates)?;
+        stream_transcriptome(stream, &transcripts, transcriptome_fasta, order, variants.as_ref().map(|v| (v, table)), &mut errors, threads)?;
+        println!("Produced {} transcripts from {} genes", transcript_count, gene_count);
+        write_stats_reports(&matches, facts, &transcripts, &errors, transcriptome_fasta, &mut outputs)?;
+        return report_errors(errors, &policy, outputs, &matches);
+    }
+
+    // Load genome into memory, or open it through its .fai index
+    let packed = matches.get_flag("packed");
+    if packed && reads_on_demand(dna_fasta)? {
+        println!("  Genome: read on demand, --packed has no effect");
+    }
+    let genome = open_genome_with(dna_fasta, packed, rename_duplicates, &mut errors)?;
+    let genome = genome.as_ref();
+    if policy.too_many_warnings(&errors) {
+        return report_errors(errors, &policy, outputs, &matches);
+    }
+
+    // Optionally write the cleaned GFF3, with the sequence lengths of the genome
+    if let Some(normalized_path) = normalized_file {
+        write_normalized_gff3(input_file, &transcripts, &errors, &renamed, Some(genome), &outputs.stage(normalized_path))?;
+    }
+
+    // Extract and write transcript sequences, personalized if a VCF is given
+    if let Some(variants) = &variants {
+        write_personal_transcriptome(genome, &transcripts, transcriptome_fasta, variants, table, &mut errors, threads)?;
     } else {
-        for e in &errors {
-            println!("[{:?}] {}", e.severity, e.message);
+        write_transcriptome(genome, &transcripts, transcriptome_fasta, threads)?;
+    }
+    if policy.too_many_warnings(&errors) {
+        return report_errors(errors, &policy, outputs, &matches);
+    }
+
+    // Optionally write every feature separately
+    if let Some(feature_fasta) = matches.get_one::<String>("feature_fasta") {
+        if matches.get_flag("unique_features") {
+            let map = matches.get_one::<String>("feature_map").map(|p| outputs.stage(p));
+            write_unique_feature_sequences(genome, &transcripts, &outputs.stage(feature_fasta), map.as_deref(), threads)?;
+        } else {
+            write_feature_sequences(genome, &transcripts, &outputs.stage(feature_fasta), threads)?;
         }
     }
 
-    if errors.iter().any(|e| matches!(e.severity, Severity::Fatal)) {
-        std::process::exit(1);
+    // Optionally check splice sites and coding sequences
+    if let (Some(qc_path), Some(cds_lines)) = (matches.get_one::<String>("qc"), &cds_lines) {
+        write_qc_table(genome, &transcripts, cds_lines, &outputs.stage(qc_path), &mut errors, threads)?;
+    }
+
+    println!("Produced {} transcripts from {} genes", transcript_count, gene_count);
+    write_stats_reports(&matches, facts, &transcripts, &errors, transcriptome_fasta, &mut outputs)?;
+
+    report_errors(errors, &policy, outputs, &matches)
+}
+
+/// Write the requested statistics reports, with the GC content of the written transcriptome.
+fn write_stats_reports(
+    matches: &ArgMatches,
+    facts: Option<AnnotationFacts>,
+    transcripts: &[Transcript],
+    errors: &[Error],
+    transcriptome_fasta: &str,
+    outputs: &mut StagedOutputs,
+) -> Result<()> {
+    let Some(facts) = facts else {
+        return Ok(());
+    };
+    let mut stats = Stats::collect(&facts, transcripts, errors);
+    stats.read_gc(transcriptome_fasta)?;
+    let mut path = |id: &str| matches.get_one::<String>(id).map(|p| outputs.stage(p));
+    let (text, json, html) = (path("stats"), path("stats_json"), path("stats_html"));
+    write_stats(&stats, text.as_deref(), json.as_deref(), html.as_deref())
+}
+
+/// Convert a BED or VCF file between genomic and transcript coordinates.
+fn run_map(matches: &ArgMatches) -> Result<()> {
+    let input_file = matches.get_one::<String>("gff3").unwrap();
+    let input = matches.get_one::<String>("input").unwrap();
+    let output = matches.get_one::<String>("output").unwrap();
+    let features = parse_features(matches);
+    let direction = if matches.get_flag("to_genome") {
+        Direction::ToGenome
+    } else {
+        Direction::ToTranscript
+    };
+
+    let policy = build_policy(matches)?;
+    let mut outputs = StagedOutputs::new(matches.get_flag("keep_partial"));
+    let output = &outputs.stage(output);
+
+    let mut errors: Vec<Error> = Vec::new();
+    let annotation = parse_gff3(input_file, &features, &mut errors)?;
+    let transcripts = build_transcripts(annotation, &mut errors);
+
+    if input.to_lowercase().ends_with(".vcf") {
+        let genome = match matches.get_one::<String>("dna") {
+            Some(path) => Some(open_genome(path)?),
+            None => None,
+        };
+        map_vcf(input, output, &transcripts, genome.as_deref(), direction, &mut errors)?;
+    } else {
+        map_bed(input, output, &transcripts, direction, &mut errors)?;
     }
 
+    report_errors(errors, &policy, outputs, matches)
+}
+
+/// Process every entry of a manifest, exiting with an error if any of them failed.
+fn run_batch_command(matches: &ArgMatches) -> Result<()> {
+    let entries = read_manifest(matches.get_one::<String>("manifest").unwrap())?;
+    let summary = matches.get_one::<String>("summary").unwrap();
+    let jobs = resolve_threads(*matches.get_one::<usize>("jobs").unwrap());
+    let threads = resolve_threads(*matches.get_one::<usize>("threads").unwrap());
+    let features = parse_features(matches);
+    let policy = build_policy(matches)?;
+
+    println!("  Entries: {}", entries.len());
+    let options = EntryOptions {
+        features: &features,
+        threads,
+        keep_partial: matches.get_flag("keep_partial"),
+        rename_duplicates: matches.get_flag("rename_duplicates"),
+        policy: &policy,
+    };
+    let failed = run_batch(&entries, &options, jobs, summary)?;
+    println!("Processed {} entries, {} failed", entries.len(), failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
+fn parse_features(matches: &ArgMatches) -> Vec<String> {
+    matches
+        .get_one::<String>("features")
+        .map(|s| s.split(',').map(|item| item.trim().to_string()).collect())
+        .unwrap_or_else(|| vec!["exon".to_string()])
+}
+
+fn error_format_arg() -> Arg {
+    Arg::new("error_format")
+        .long("error-format")
+        .value_name("text|jsonl|tsv")
+        .value_parser(["text", "jsonl", "tsv"])
+        .default_value("text")
+        .help("Format of the warnings and errors: text, JSON Lines or TSV")
+}
+
+fn rename_duplicates_arg() -> Arg {
+    Arg::new("rename_duplicates")
+        .long("rename-duplicates")
+        .action(ArgAction::SetTrue)
+        .help("Rename repeated GFF3 IDs and genome sequence names with a _dup<N> suffix instead of failing")
+}
+
+fn keep_partial_arg() -> Arg {
+    Arg::new("keep_partial")
+        .long("keep-partial")
+        .action(ArgAction::SetTrue)
+        .help("Keep the outputs of a failed run instead of removing them")
+}
 
+fn policy_args() -> Vec<Arg> {
+    let codes = |id: &'static str, long: &'static str, help: &'static str| {
+        Arg::new(id).long(long).value_name("CODES").action(ArgAction::Append).help(help)
+    };
+    vec![
+        Arg::new("strict")
+            .long("strict")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("lenient")
+            .help("Treat every warning as fatal"),
+        Arg::new("lenient")
+            .long("lenient")
+            .action(ArgAction::SetTrue)
+            .help("Treat every fatal problem as a warning"),
+        Arg::new("severity_config")
+            .long("severity-config")
+            .value_name("CONFIG")
+            .help("Read severity overrides and the warning limit from this file"),
+        codes("fatal_codes", "fatal", "Make the problems with these comma-separated codes fatal"),
+        codes("warn_codes", "warn", "Make the problems with these comma-separated codes warnings"),
+        codes("ignore_codes", "ignore", "Do not report the problems with these comma-separated codes"),
+        Arg::new("max_warnings")
+            .long("max-warnings")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("Fail once more than N warnings are reported"),
+    ]
+}
+
+/// The severity policy from the presets, then the config file, then the per-code flags.
+fn build_policy(matches: &ArgMatches) -> Result<Policy> {
+    let mut policy = if matches.get_flag("strict") {
+        Policy::strict()
+    } else if matches.get_flag("lenient") {
+        Policy::lenient()
+    } else {
+        Policy::default()
+    };
+    if let Some(path) = matches.get_one::<String>("severity_config") {
+        policy.load(path)?;
+    }
+    for (id, level) in [("fatal_codes", Some(Severity::Fatal)), ("warn_codes", Some(Severity::Warning)), ("ignore_codes", None)] {
+        for codes in matches.get_many::<String>(id).into_iter().flatten() {
+            policy.set_codes(codes, level)?;
+        }
+    }
+    if let Some(&max) = matches.get_one::<usize>("max_warnings") {
+        policy.set_max_warnings(max);
+    }
+    Ok(policy)
+}
+
+/// Print or log the collected problems under the severity policy. If any are fatal, the
+/// outputs are removed (unless `--keep-partial`) and the process exits with an error.
+fn report_errors(mut errors: Vec<Error>, policy: &Policy, outputs: StagedOutputs, matches: &ArgMatches) -> Result<()> {
+    policy.judge(&mut errors);
+    let fatal = errors.iter().any(|e| matches!(e.severity, Severity::Fatal));
+    outputs.finish(!fatal)?;
+    let format = matches
+        .get_one::<String>("error_format")
+        .and_then(|f| ReportFormat::from_name(f))
+        .unwrap_or(ReportFormat::Text);
+    if let Some(path) = matches.get_one::<String>("error") {
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        write_report(&errors, format, &mut out)?;
+        out.flush()?;
+    } else {
+        write_report(&errors, format, &mut std::io::stdout())?;
+    }
+
+    if fatal {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
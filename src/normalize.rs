@@ -0,0 +1,357 @@
+use crate::error::Error;
+use crate::genome::GenomeSource;
+use crate::structures::Transcript;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// A feature line of the source GFF3. IDs are kept as written, or as renamed while parsing,
+/// for matching with the parsed regions; attributes are re-encoded on output.
+struct Record {
+    columns: Vec<String>,
+    id: Option<String>,
+    parents: Vec<String>,
+    start: usize,
+    end: usize,
+}
+
+#[derive(Default)]
+struct TranscriptGroup {
+    line: Option<Record>,
+    children: Vec<Record>,
+}
+
+#[derive(Default)]
+struct GeneGroup {
+    line: Option<Record>,
+    transcripts: HashMap<String, TranscriptGroup>,
+}
+
+impl GeneGroup {
+    fn records(&self) -> impl Iterator<Item = &Record> {
+        self.line.iter().chain(
+            self.transcripts
+                .values()
+                .flat_map(|t| t.line.iter().chain(t.children.iter())),
+        )
+    }
+
+    fn seqid(&self) -> &str {
+        self.records().next().map_or("", |r| r.columns[0].as_str())
+    }
+
+    fn span(&self) -> (usize, usize) {
+        match &self.line {
+            Some(line) => (line.start, line.end),
+            None => (
+                self.records().map(|r| r.start).min().unwrap_or(0),
+                self.records().map(|r| r.end).max().unwrap_or(0),
+            ),
+        }
+    }
+}
+
+/// Write a cleaned GFF3 containing the genes of the accepted transcripts, sorted by seqid
+/// and position and grouped by gene, with re-encoded attributes. Lines carry the IDs and
+/// parents given to them while parsing (`renamed`, from `Annotation::renamed`). Seqids
+/// with a length in `genome` get a `##sequence-region` header.
+/// Transcripts that were rejected are commented out together with the problems that rejected them.
+/// Features not attached to any transcript are left out.
+pub fn write_normalized_gff3(
+    gff3_path: &str,
+    transcripts: &[Transcript],
+    errors: &[Error],
+    renamed: &HashMap<usize, (Option<String>, Option<String>)>,
+    genome: Option<&dyn GenomeSource>,
+    out_path: &str,
+) -> anyhow::Result<()> {
+    let records = read_records(gff3_path, renamed)?;
+
+    let accepted: HashSet<&str> = transcripts.iter().map(|t| t.id.as_str()).collect();
+    let mut gene_of: HashMap<String, String> = transcripts
+        .iter()
+        .filter_map(|t| t.gene_id.clone().map(|g| (t.id.clone(), g)))
+        .collect();
+    let mut known: HashSet<String> = accepted.iter().map(|s| s.to_string()).collect();
+    let mut reasons: HashMap<&str, Vec<&str>> = HashMap::new();
+    for e in errors {
+        if let Some(transcript_id) = &e.transcript_id {
+            known.insert(transcript_id.clone());
+            if e.rejects_transcript() {
+                reasons.entry(transcript_id.as_str()).or_default().push(e.message.as_str());
+            }
+        }
+    }
+
+    for r in &records {
+        if is_transcript_type(&r.columns[2])
+            && let Some(id) = &r.id
+        {
+            known.insert(id.clone());
+            gene_of
+                .entry(id.clone())
+                .or_insert_with(|| r.parents.first().unwrap_or(id).clone());
+        }
+    }
+
+    // Group the records by gene, then by transcript
+    let mut genes: HashMap<String, GeneGroup> = HashMap::new();
+    for record in records {
+        let gene_for = |tx: &str| gene_of.get(tx).cloned().unwrap_or_else(|| tx.to_string());
+        if record.columns[2] == "gene" {
+            if let Some(id) = record.id.clone() {
+                genes.entry(id).or_default().line = Some(record);
+            }
+        } else if let Some(id) = record.id.clone().filter(|id| {
+            known.contains(id) && (is_transcript_type(&record.columns[2]) || record.parents.is_empty())
+        }) {
+            let gene = genes.entry(gene_for(&id)).or_default();
+            gene.transcripts.entry(id).or_default().line = Some(record);
+        } else if let Some(parent) = record
+            .parents
+            .iter()
+            .find(|p| accepted.contains(p.as_str()))
+            .or_else(|| record.parents.iter().find(|p| known.contains(*p)))
+            .cloned()
+        {
+            let gene = genes.entry(gene_for(&parent)).or_default();
+            gene.transcripts.entry(parent).or_default().children.push(record);
+        }
+    }
+    genes.retain(|_, g| !g.transcripts.is_empty());
+
+    let mut order: Vec<(&String, &GeneGroup)> = genes.iter().collect();
+    order.sort_by(|(a_id, a), (b_id, b)| {
+        (a.seqid(), a.span(), a_id).cmp(&(b.seqid(), b.span(), b_id))
+    });
+
+    let mut sequence_regions: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, gene) in &order {
+        if gene.transcripts.keys().any(|t| accepted.contains(t.as_str()))
+            && let Some(length) = genome.and_then(|g| g.length(gene.seqid()))
+        {
+            sequence_regions.insert(gene.seqid(), length);
+        }
+    }
+
+    let mut writer = BufWriter::new(File::create(out_path)?);
+    writeln!(writer, "##gff-version 3")?;
+    for (seqid, length) in &sequence_regions {
+        writeln!(writer, "##sequence-region {} 1 {}", seqid, length)?;
+    }
+
+    for (gene_id, gene) in order {
+        let gene_accepted = gene.transcripts.keys().any(|t| accepted.contains(t.as_str()));
+        if let Some(line) = &gene.line {
+            if !gene_accepted {
+                writeln!(writer, "# rejected gene {}: no valid transcripts", gene_id)?;
+            }
+            write_record(&mut writer, line, !gene_accepted)?;
+        }
+
+        let mut tx_order: Vec<(&String, &TranscriptGroup)> = gene.transcripts.iter().collect();
+        tx_order.sort_by_key(|(id, t)| {
+            let start = t.line.iter().chain(t.children.iter()).map(|r| r.start).min();
+            let end = t.line.iter().chain(t.children.iter()).map(|r| r.end).max();
+            (start, end, *id)
+        });
+
+        for (tx_id, tx) in tx_order {
+            let rejected = !accepted.contains(tx_id.as_str());
+            if rejected {
+                let reason = reasons
+                    .get(tx_id.as_str())
+                    .map_or("no features of the requested types".to_string(), |r| r.join("; "));
+                writeln!(writer, "# rejected transcript {}: {}", tx_id, reason.replace('\n', " "))?;
+            }
+            if let Some(line) = &tx.line {
+                write_record(&mut writer, line, rejected)?;
+            }
+            let mut children: Vec<&Record> = tx.children.iter().collect();
+            children.sort_by_key(|r| (r.start, r.end));
+            for child in children {
+                write_record(&mut writer, child, rejected)?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn is_transcript_type(feature_type: &str) -> bool {
+    matches!(feature_type, "mRNA" | "transcript")
+}
+
+fn write_record(writer: &mut impl Write, record: &Record, commented: bool) -> std::io::Result<()> {
+    if commented {
+        write!(writer, "# ")?;
+    }
+    writeln!(writer, "{}", record.columns.join("\t"))
+}
+
+fn read_records(gff3_path: &str, renamed: &HashMap<usize, (Option<String>, Option<String>)>) -> anyhow::Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(gff3_path)?);
+    let mut records = Vec::new();
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with("##FASTA") {
+            break;
+        }
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let mut columns: Vec<String> = line.split('\t').map(|c| c.to_owned()).collect();
+        if columns.len() != 9 {
+            continue;
+        }
+
+        let (Ok(start), Ok(end)) = (columns[3].parse::<usize>(), columns[4].parse::<usize>()) else {
+            continue;
+        };
+
+        let (new_id, new_parent) = match renamed.get(&(line_no + 1)) {
+            Some((id, parent)) => (id.as_deref(), parent.as_deref()),
+            None => (None, None),
+        };
+        let mut id = None;
+        let mut parents = Vec::new();
+        let mut attributes = Vec::new();
+        for attr in columns[8].trim().trim_end_matches(';').split(';') {
+            let Some((key, value)) = attr.split_once('=') else {
+                continue;
+            };
+            let (key, mut value) = (key.trim(), value.trim());
+            match key {
+                "ID" => {
+                    value = new_id.unwrap_or(value);
+                    id = Some(value.to_owned());
+                }
+                "Parent" => {
+                    value = new_parent.unwrap_or(value);
+                    parents = value.split(',').map(|p| p.to_owned()).collect();
+                }
+                _ => (),
+            }
+            let values: Vec<String> = value
+                .split(',')
+                .map(|v| escape_attribute(&unescape_attribute(v)))
+                .collect();
+            attributes.push(format!("{}={}", escape_attribute(&unescape_attribute(key)), values.join(",")));
+        }
+        // A synthesized ID, for a line without one
+        if id.is_none()
+            && let Some(new_id) = new_id
+        {
+            id = Some(new_id.to_owned());
+            attributes.insert(0, format!("ID={}", escape_attribute(&unescape_attribute(new_id))));
+        }
+        columns[8] = attributes.join(";");
+
+        records.push(Record { columns, id, parents, start, end });
+    }
+
+    Ok(records)
+}
+
+/// Decode `%XX` escapes of a GFF3 attribute tag or value. Malformed escapes are kept as is.
+pub fn unescape_attribute(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && let Some(byte) = std::str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escape the characters with reserved meaning in GFF3 column 9, and control characters.
+pub fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' | '=' | '&' | ',' | '%' | '\u{7f}' => escaped.push_str(&format!("%{:02X}", c as u32)),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("%{:02X}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attribute_escaping_round_trip() {
+        assert_eq!(unescape_attribute("a%2Cb%3dc"), "a,b=c");
+        assert_eq!(escape_attribute("a,b=c;d%\t"), "a%2Cb%3Dc%3Bd%25%09");
+        assert_eq!(unescape_attribute("100%"), "100%");
+    }
+
+    #[test]
+    fn test_write_normalized_gff3() {
+        let dir = tempfile::tempdir().unwrap();
+        let gff3 = dir.path().join("in.gff3");
+        let out = dir.path().join("out.gff3");
+        std::fs::write(
+            &gff3,
+            "##gff-version 3\n\
+             chr2\tsrc\tgene\t1\t60\t.\t+\t.\tID=g3\n\
+             chr2\tsrc\tmRNA\t1\t60\t.\t+\t.\tID=tx3;Parent=g3\n\
+             chr2\tsrc\texon\t1\t50\t.\t+\t.\tID=ex5;Parent=tx3\n\
+             chr1\tsrc\tgene\t100\t300\t.\t+\t.\tID=g1;Name=a%2cb\n\
+             chr1\tsrc\tmRNA\t100\t300\t.\t+\t.\tID=tx1;Parent=g1\n\
+             chr1\tsrc\texon\t200\t300\t.\t+\t.\tID=ex2;Parent=tx1\n\
+             chr1\tsrc\texon\t100\t150\t.\t+\t.\tParent=tx1\n\
+             chr1\tsrc\tregion\t1\t1000\t.\t+\t.\tID=chr1\n\
+             chr1\tsrc\tgene\t500\t700\t.\t+\t.\tID=g2\n\
+             chr1\tsrc\tmRNA\t500\t700\t.\t+\t.\tID=tx1;Parent=g2\n\
+             chr1\tsrc\texon\t500\t550\t.\t+\t.\tID=ex6;Parent=tx1\n\
+             chr1\tsrc\texon\t600\t700\t.\t+\t.\tID=ex7;Parent=tx1\n",
+        )
+        .unwrap();
+
+        let mut errors = Vec::new();
+        let mut annotation =
+            crate::gff3::parse_gff3_parallel(gff3.to_str().unwrap(), &["exon".to_string()], &mut errors, 1, true).unwrap();
+        let renamed = std::mem::take(&mut annotation.renamed);
+        let transcripts = crate::transcript_builder::build_transcripts(annotation, &mut errors);
+        // chr2 is not in the genome, so it has no ##sequence-region
+        let genome: HashMap<String, Vec<u8>> = [("chr1".to_string(), vec![b'A'; 1200])].into_iter().collect();
+        write_normalized_gff3(gff3.to_str().unwrap(), &transcripts, &errors, &renamed, Some(&genome), out.to_str().unwrap()).unwrap();
+
+        let written = std::fs::read_to_string(&out).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines, vec![
+            "##gff-version 3",
+            "##sequence-region chr1 1 1200",
+            "chr1\tsrc\tgene\t100\t300\t.\t+\t.\tID=g1;Name=a%2Cb",
+            "chr1\tsrc\tmRNA\t100\t300\t.\t+\t.\tID=tx1;Parent=g1",
+            "chr1\tsrc\texon\t100\t150\t.\t+\t.\tID=tx1.exon1;Parent=tx1",
+            "chr1\tsrc\texon\t200\t300\t.\t+\t.\tID=ex2;Parent=tx1",
+            "chr1\tsrc\tgene\t500\t700\t.\t+\t.\tID=g2",
+            "chr1\tsrc\tmRNA\t500\t700\t.\t+\t.\tID=tx1_dup2;Parent=g2",
+            "chr1\tsrc\texon\t500\t550\t.\t+\t.\tID=ex6;Parent=tx1_dup2",
+            "chr1\tsrc\texon\t600\t700\t.\t+\t.\tID=ex7;Parent=tx1_dup2",
+            "# rejected gene g3: no valid transcripts",
+            "# chr2\tsrc\tgene\t1\t60\t.\t+\t.\tID=g3",
+            // Not the span warning of tx3, which is not why it was rejected
+            "# rejected transcript tx3: Transcript tx3 has only one feature; skipping",
+            "# chr2\tsrc\tmRNA\t1\t60\t.\t+\t.\tID=tx3;Parent=g3",
+            "# chr2\tsrc\texon\t1\t50\t.\t+\t.\tID=ex5;Parent=tx3",
+        ]);
+    }
+}
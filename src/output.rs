@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// Output files written under temporary names next to their final paths, and renamed
+/// together once the run is known to have succeeded. A failed run, or one that stops with
+/// an error, removes them instead, so no complete-looking output is left behind. With
+/// `keep_partial`, the outputs of a failed run are renamed as well, for debugging.
+pub struct StagedOutputs {
+    staged: Vec<(String, String)>, // temporary and final path
+    keep_partial: bool,
+}
+
+impl StagedOutputs {
+    pub fn new(keep_partial: bool) -> Self {
+        StagedOutputs { staged: Vec::new(), keep_partial }
+    }
+
+    /// The temporary path to write the output `path` to: a hidden file in the same
+    /// directory, so that the rename does not cross file systems.
+    pub fn stage(&mut self, path: &str) -> String {
+        let final_path = Path::new(path);
+        let name = final_path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        let temporary = final_path
+            .with_file_name(format!(".{}.{}.partial", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        self.staged.push((temporary.clone(), path.to_string()));
+        temporary
+    }
+
+    /// Rename the outputs to their final paths if the run succeeded, or with `keep_partial`;
+    /// remove them otherwise. Outputs that were never written are skipped.
+    pub fn finish(mut self, success: bool) -> Result<()> {
+        let keep = success || self.keep_partial;
+        for (temporary, path) in std::mem::take(&mut self.staged) {
+            if !Path::new(&temporary).exists() {
+                continue;
+            }
+            if keep {
+                std::fs::rename(&temporary, &path).with_context(|| format!("Cannot move output to {}", path))?;
+            } else {
+                std::fs::remove_file(&temporary)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for StagedOutputs {
+    fn drop(&mut self) {
+        // Not finished: the run stopped with an error
+        for (temporary, path) in self.staged.drain(..) {
+            if self.keep_partial {
+                std::fs::rename(&temporary, &path).ok();
+            } else {
+                std::fs::remove_file(&temporary).ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outputs_are_moved_only_on_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        let listing = || {
+            let mut names: Vec<String> =
+                std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+            names.sort();
+            names
+        };
+
+        for (success, keep_partial, expected) in [
+            (true, false, vec!["a.fa", "b.tsv"]),
+            (false, false, vec![]),
+            (false, true, vec!["a.fa", "b.tsv"]),
+        ] {
+            let mut outputs = StagedOutputs::new(keep_partial);
+            let staged = [outputs.stage(&path("a.fa")), outputs.stage(&path("b.tsv")), outputs.stage(&path("never.bed"))];
+            std::fs::write(&staged[0], ">t\nACGT\n").unwrap();
+            std::fs::write(&staged[1], "t\tg\n").unwrap();
+            assert!(!Path::new(&path("a.fa")).exists());
+            outputs.finish(success).unwrap();
+            assert_eq!(listing(), expected);
+            for name in expected {
+                std::fs::remove_file(path(name)).unwrap();
+            }
+        }
+
+        // Dropped without finishing, as when the run stops with an error
+        let mut outputs = StagedOutputs::new(false);
+        std::fs::write(outputs.stage(&path("a.fa")), "").unwrap();
+        drop(outputs);
+        assert!(listing().is_empty());
+    }
+}
@@ -0,0 +1,149 @@
+use crate::duplicates::SequenceNames;
+use crate::genome::{missing_chromosome, out_of_range, GenomeSource};
+use anyhow::Result;
+use bio::io::fasta::{self, FastaRead};
+use std::collections::HashMap;
+
+const BASES: [u8; 4] = *b"ACGT";
+
+/// A sequence stored with 2 bits per base. N runs and lowercase (soft-masked) runs are kept
+/// as half-open intervals, and any other character (IUPAC codes, gaps) is kept as it was,
+/// so the original sequence is reproduced exactly.
+#[derive(Debug, Default)]
+pub struct PackedSequence {
+    length: usize,
+    bases: Vec<u8>,
+    n_blocks: Vec<(usize, usize)>,
+    mask_blocks: Vec<(usize, usize)>,
+    others: Vec<(usize, u8)>,
+}
+
+impl PackedSequence {
+    pub fn new(seq: &[u8]) -> Self {
+        let mut packed = PackedSequence {
+            length: seq.len(),
+            bases: vec![0; seq.len().div_ceil(4)],
+            ..Default::default()
+        };
+        for (i, &b) in seq.iter().enumerate() {
+            let code = match b.to_ascii_uppercase() {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                b'N' => {
+                    extend_block(&mut packed.n_blocks, i);
+                    0
+                }
+                _ => {
+                    packed.others.push((i, b));
+                    0
+                }
+            };
+            packed.bases[i / 4] |= code << ((i % 4) * 2);
+            if b.is_ascii_lowercase() {
+                extend_block(&mut packed.mask_blocks, i);
+            }
+        }
+        packed
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Append the bases of the 0-based, half-open range `start..end`.
+    pub fn decode(&self, start: usize, end: usize, out: &mut Vec<u8>) {
+        let from = out.len();
+        out.extend((start..end).map(|i| BASES[((self.bases[i / 4] >> ((i % 4) * 2)) & 3) as usize]));
+        let bases = &mut out[from..];
+        for &(s, e) in overlapping(&self.n_blocks, start, end) {
+            bases[s.max(start) - start..e.min(end) - start].fill(b'N');
+        }
+        for &(s, e) in overlapping(&self.mask_blocks, start, end) {
+            bases[s.max(start) - start..e.min(end) - start].make_ascii_lowercase();
+        }
+        let first = self.others.partition_point(|&(i, _)| i < start);
+        for &(i, b) in self.others[first..].iter().take_while(|&&(i, _)| i < end) {
+            bases[i - start] = b;
+        }
+    }
+}
+
+/// Extend the last block if it ends at `i`, or start a new one.
+fn extend_block(blocks: &mut Vec<(usize, usize)>, i: usize) {
+    match blocks.last_mut() {
+        Some(last) if last.1 == i => last.1 += 1,
+        _ => blocks.push((i, i + 1)),
+    }
+}
+
+/// Blocks of a sorted, non-overlapping list that overlap `start..end`.
+pub(crate) fn overlapping(blocks: &[(usize, usize)], start: usize, end: usize) -> &[(usize, usize)] {
+    let first = blocks.partition_point(|b| b.1 <= start);
+    let last = blocks.partition_point(|b| b.0 < end);
+    &blocks[first..last.max(first)]
+}
+
+/// The whole genome in memory, at about a quarter of the size of `load_genome_to_memory`.
+#[derive(Debug, Default)]
+pub struct PackedGenome {
+    sequences: HashMap<String, PackedSequence>,
+}
+
+impl PackedGenome {
+    /// Load and pack a genome FASTA, one record at a time, naming records through `names`.
+    pub fn load(fasta_path: &str, names: &mut SequenceNames) -> Result<Self> {
+        let mut reader = fasta::Reader::from_file(fasta_path)?;
+        let mut record = fasta::Record::new();
+        let mut genome = PackedGenome::default();
+        loop {
+            reader.read(&mut record)?;
+            if record.is_empty() {
+                break;
+            }
+            if let Some(name) = names.name(record.id()) {
+                genome.sequences.insert(name, PackedSequence::new(record.seq()));
+            }
+        }
+        Ok(genome)
+    }
+}
+
+impl GenomeSource for PackedGenome {
+    fn fetch(&self, chromosome: &str, start: usize, end: usize, out: &mut Vec<u8>) -> Result<()> {
+        let sequence = self.sequences.get(chromosome).ok_or_else(|| missing_chromosome(chromosome))?;
+        if start == 0 || start > end + 1 || end > sequence.len() {
+            return Err(out_of_range(chromosome, start, end));
+        }
+        sequence.decode(start - 1, end, out);
+        Ok(())
+    }
+
+    fn length(&self, chromosome: &str) -> Option<usize> {
+        self.sequences.get(chromosome).map(|s| s.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed_sequence_is_exact() {
+        let seq = b"ACGTacgtNNNnnNAcRyGT-aaNNtgcA";
+        let packed = PackedSequence::new(seq);
+        assert_eq!(packed.n_blocks, vec![(8, 14), (23, 25)]);
+        for start in 0..seq.len() {
+            for end in start..=seq.len() {
+                let mut out = b">".to_vec();
+                packed.decode(start, end, &mut out);
+                assert_eq!(&out[1..], &seq[start..end], "{}..{}", start, end);
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use anyhow::Result;
+use std::thread;
+
+/// Items given to each worker thread per batch in `for_each_in_order`.
+const BATCH_PER_THREAD: usize = 1024;
+
+/// Number of worker threads for a `--threads` value, where 0 means one per available core.
+pub fn resolve_threads(requested: usize) -> usize {
+    match requested {
+        0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        n => n,
+    }
+}
+
+/// Apply `f` to every item, splitting the items into contiguous chunks over `threads`
+/// scoped threads. Results are in the order of the items, whatever the number of threads.
+pub fn map_in_order<T: Sync, R: Send>(items: &[T], threads: usize, f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    if threads <= 1 || items.len() <= 1 {
+        return items.iter().map(f).collect();
+    }
+    let chunk = items.len().div_ceil(threads);
+    let f = &f;
+    thread::scope(|scope| {
+        let workers: Vec<_> = items
+            .chunks(chunk)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(f).collect::<Vec<R>>()))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Apply `f` to the items in batches with `map_in_order`, and pass each item with its result
+/// to `consume` in order. Only one batch of results is held at a time. Stops at the first
+/// error of `consume`, as a single-threaded loop would.
+pub fn for_each_in_order<T: Sync, R: Send>(
+    items: &[T],
+    threads: usize,
+    f: impl Fn(&T) -> R + Sync,
+    mut consume: impl FnMut(&T, R) -> Result<()>,
+) -> Result<()> {
+    for batch in items.chunks(threads.max(1) * BATCH_PER_THREAD) {
+        for (item, result) in batch.iter().zip(map_in_order(batch, threads, &f)) {
+            consume(item, result)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_in_order_keeps_order() {
+        let items: Vec<usize> = (0..1000).collect();
+        let single = map_in_order(&items, 1, |i| i * 3);
+        for threads in [2, 3, 7, 2000] {
+            assert_eq!(map_in_order(&items, threads, |i| i * 3), single);
+        }
+
+        let mut seen = Vec::new();
+        for_each_in_order(&items, 4, |i| i + 1, |_, r| {
+            seen.push(r);
+            anyhow::ensure!(r < 500, "stop");
+            Ok(())
+        })
+        .unwrap_err();
+        assert_eq!(seen, (1..=500).collect::<Vec<_>>());
+    }
+}
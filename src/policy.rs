@@ -0,0 +1,190 @@
+use crate::batch::toml_string;
+use crate::error::{Code, Error, Severity};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+/// How the problems of a run are judged: the severity of each code, and how many warnings
+/// are tolerated. Severities only decide whether the run fails; a rejected transcript stays
+/// rejected even if its problem is downgraded or silenced.
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    /// Severity given to every code without an override, from `--strict` or `--lenient`.
+    preset: Option<Severity>,
+    /// Severity per code; None silences the code.
+    overrides: HashMap<Code, Option<Severity>>,
+    max_warnings: Option<usize>,
+}
+
+/// A severity level by name: `fatal`, `warning` or `ignore`. Silencing is `Some(None)`.
+pub fn parse_level(name: &str) -> Option<Option<Severity>> {
+    match name.to_ascii_lowercase().as_str() {
+        "fatal" | "error" => Some(Some(Severity::Fatal)),
+        "warning" | "warn" => Some(Some(Severity::Warning)),
+        "ignore" | "silence" | "off" => Some(None),
+        _ => None,
+    }
+}
+
+impl Policy {
+    /// Make every problem fatal.
+    pub fn strict() -> Self {
+        Policy { preset: Some(Severity::Fatal), ..Default::default() }
+    }
+
+    /// Make every problem a warning.
+    pub fn lenient() -> Self {
+        Policy { preset: Some(Severity::Warning), ..Default::default() }
+    }
+
+    /// Give a code a severity, or silence it with None.
+    pub fn set(&mut self, code: Code, level: Option<Severity>) {
+        self.overrides.insert(code, level);
+    }
+
+    /// Abort once more than this many warnings are reported.
+    pub fn set_max_warnings(&mut self, max: usize) {
+        self.max_warnings = Some(max);
+    }
+
+    /// Set a comma-separated list of codes to a level.
+    pub fn set_codes(&mut self, codes: &str, level: Option<Severity>) -> Result<()> {
+        for name in codes.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+            let code = Code::from_name(name).with_context(|| format!("Unknown diagnostic code '{}'", name))?;
+            self.set(code, level);
+        }
+        Ok(())
+    }
+
+    /// Read overrides from a config file:
+    ///
+    /// ```toml
+    /// preset = "strict"        # or "lenient"
+    /// max_warnings = 100
+    ///
+    /// [severity]
+    /// OVERLAPPING_REGIONS = "fatal"
+    /// SHORT_REGION = "ignore"
+    /// ```
+    ///
+    /// Settings already made are kept unless the file changes them.
+    pub fn load(&mut self, path: &str) -> Result<()> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Cannot read {}", path))?;
+        let mut in_severity = false;
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || format!("{}:{}: cannot read '{}'", path, line_no + 1, line);
+            if line.starts_with('[') {
+                in_severity = match line {
+                    "[severity]" => true,
+                    _ => anyhow::bail!("{}: unknown table", bad()),
+                };
+                continue;
+            }
+            let (key, value) = line.split_once('=').with_context(bad)?;
+            let key = key.trim();
+            let value = value.split(" #").next().unwrap_or_default().trim();
+            let string = || toml_string(value).with_context(bad);
+            match (in_severity, key) {
+                (true, code) => {
+                    let code = Code::from_name(code).with_context(|| format!("{}: unknown diagnostic code", bad()))?;
+                    let level = parse_level(&string()?).with_context(|| format!("{}: unknown severity", bad()))?;
+                    self.set(code, level);
+                }
+                (false, "preset") => {
+                    self.preset = match string()?.as_str() {
+                        "strict" => Some(Severity::Fatal),
+                        "lenient" => Some(Severity::Warning),
+                        _ => anyhow::bail!("{}: the preset is strict or lenient", bad()),
+                    }
+                }
+                (false, "max_warnings") => self.max_warnings = Some(value.parse().with_context(bad)?),
+                _ => anyhow::bail!("{}: unknown setting", bad()),
+            }
+        }
+        Ok(())
+    }
+
+    /// The severity of a problem under this policy, or None if it is silenced.
+    pub fn severity(&self, error: &Error) -> Option<Severity> {
+        match self.overrides.get(&error.code) {
+            Some(level) => *level,
+            None => Some(self.preset.unwrap_or(error.severity)),
+        }
+    }
+
+    /// Change the severity of the problems and drop the silenced ones.
+    pub fn apply(&self, errors: &mut Vec<Error>) {
+        errors.retain_mut(|e| match self.severity(e) {
+            Some(severity) => {
+                e.severity = severity;
+                true
+            }
+            None => false,
+        });
+    }
+
+    /// Whether the problems contain more warnings than allowed.
+    pub fn too_many_warnings(&self, errors: &[Error]) -> bool {
+        let warnings = errors.iter().filter(|e| self.severity(e) == Some(Severity::Warning)).count();
+        self.max_warnings.is_some_and(|max| warnings > max)
+    }
+
+    /// Apply the policy, then add a fatal problem if more warnings than allowed remain.
+    pub fn judge(&self, errors: &mut Vec<Error>) {
+        self.apply(errors);
+        if let Some(max) = self.max_warnings
+            && self.too_many_warnings(errors)
+        {
+            let warnings = errors.iter().filter(|e| e.severity == Severity::Warning).count();
+            errors.push(Error::fatal(
+                Code::TooManyWarnings,
+                format!("{} warnings exceed the limit of {}", warnings, max),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_presets_and_warning_limit() {
+        let problems = || {
+            vec![
+                Error::fatal(Code::OverlappingRegions, "overlap"),
+                Error::warning(Code::ShortRegion, "short"),
+                Error::warning(Code::SingleRegion, "single"),
+            ]
+        };
+        let severities = |errors: &[Error]| errors.iter().map(|e| (e.code, e.severity)).collect::<Vec<_>>();
+
+        let mut policy = Policy::lenient();
+        policy.set_codes("SHORT_REGION", None).unwrap();
+        let mut errors = problems();
+        policy.judge(&mut errors);
+        assert_eq!(severities(&errors), [(Code::OverlappingRegions, Severity::Warning), (Code::SingleRegion, Severity::Warning)]);
+
+        let mut policy = Policy::default();
+        policy.set_codes("SINGLE_REGION", Some(Severity::Fatal)).unwrap();
+        policy.set_max_warnings(0);
+        let mut errors = problems();
+        policy.judge(&mut errors);
+        assert_eq!(errors.iter().filter(|e| e.severity == Severity::Fatal).count(), 3);
+        assert_eq!(errors.last().unwrap().code, Code::TooManyWarnings);
+        assert!(policy.set_codes("NO_SUCH_CODE", None).is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("severity.toml");
+        std::fs::write(&path, "# draft assembly\npreset = \"strict\"\nmax_warnings = 5\n\n[severity]\nSHORT_REGION = \"warning\" # tolerated\nSINGLE_REGION = 'ignore'\n").unwrap();
+        let mut policy = Policy::default();
+        policy.load(path.to_str().unwrap()).unwrap();
+        let mut errors = problems();
+        policy.judge(&mut errors);
+        assert_eq!(severities(&errors), [(Code::OverlappingRegions, Severity::Fatal), (Code::ShortRegion, Severity::Warning)]);
+        assert_eq!(policy.max_warnings, Some(5));
+    }
+}
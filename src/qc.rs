@@ -0,0 +1,317 @@
+use crate::error::{Code, Error};
+use crate::genome::GenomeSource;
+use crate::parallel::for_each_in_order;
+use crate::structures::{Annotation, Strand, Transcript};
+use anyhow::Result;
+use bio::alphabets::dna;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Intron motifs (donor-acceptor) accepted as canonical.
+const CANONICAL_MOTIFS: [&str; 3] = ["GT-AG", "GC-AG", "AT-AC"];
+
+const STOP_CODONS: [&[u8]; 3] = [b"TAA", b"TAG", b"TGA"];
+
+/// Motif of an intron too short for separate donor and acceptor sites.
+const NO_MOTIF: &str = "NA";
+
+/// CDS lines per transcript ID, as start, end and phase, kept for QC because
+/// `build_transcripts` consumes the annotation. The IDs are those of the built transcripts,
+/// after `--rename-duplicates`.
+#[derive(Debug, Default)]
+pub struct CdsLines(HashMap<String, Vec<(usize, usize, Option<u8>)>>);
+
+impl CdsLines {
+    pub fn new(annotation: &Annotation) -> Self {
+        let mut lines: HashMap<String, Vec<(usize, usize, Option<u8>)>> = HashMap::new();
+        for cds in &annotation.cds {
+            lines.entry(cds.transcript_id.to_string()).or_default().push((cds.start, cds.end, cds.phase));
+        }
+        CdsLines(lines)
+    }
+}
+
+/// QC results of one transcript.
+#[derive(Debug, Default, PartialEq)]
+pub struct TranscriptQc {
+    /// Donor-acceptor motif of each intron, in transcript order.
+    pub motifs: Vec<String>,
+    pub cds: Option<CdsQc>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct CdsQc {
+    pub length: usize,
+    pub start_codon: bool,
+    pub stop_codon: bool,
+    pub internal_stops: usize,
+    pub length_multiple_of_3: bool,
+    pub phase_consistent: bool,
+}
+
+impl TranscriptQc {
+    pub fn non_canonical(&self) -> usize {
+        self.motifs.iter().filter(|m| !CANONICAL_MOTIFS.contains(&m.as_str())).count()
+    }
+
+    /// Report what failed as warnings for the transcript.
+    fn problems(&self, transcript: &Transcript) -> Vec<Error> {
+        let id = &transcript.id;
+        let mut problems = Vec::new();
+        let mut warn = |code, message: String| {
+            let mut error = Error::warning(code, message).for_transcript(id);
+            if let Some(gene_id) = &transcript.gene_id {
+                error = error.for_gene(gene_id);
+            }
+            problems.push(error);
+        };
+        if self.non_canonical() > 0 {
+            let introns: Vec<String> = self
+                .motifs
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| !CANONICAL_MOTIFS.contains(&m.as_str()))
+                .map(|(i, m)| format!("{} ({})", i + 1, m))
+                .collect();
+            warn(Code::NonCanonicalSpliceSite, format!("Transcript {} has non-canonical introns: {}", id, introns.join(", ")));
+        }
+        if let Some(cds) = &self.cds {
+            if !cds.start_codon {
+                warn(Code::MissingStartCodon, format!("CDS of transcript {} does not start with ATG", id));
+            }
+            if !cds.stop_codon {
+                warn(Code::MissingStopCodon, format!("CDS of transcript {} does not end with a stop codon", id));
+            }
+            if cds.internal_stops > 0 {
+                warn(Code::InternalStopCodon, format!("CDS of transcript {} has {} internal stop codon(s)", id, cds.internal_stops));
+            }
+            if !cds.length_multiple_of_3 {
+                warn(Code::CdsLength, format!("CDS of transcript {} is {} bases long, not a multiple of 3", id, cds.length));
+            }
+            if !cds.phase_consistent {
+                warn(Code::InconsistentPhase, format!("CDS phases of transcript {} do not follow from the CDS lengths", id));
+            }
+        }
+        problems
+    }
+}
+
+/// Check the intron motifs of a transcript and, if it has CDS lines, its coding sequence.
+pub fn check_transcript(genome: &dyn GenomeSource, transcript: &Transcript, cds_lines: &CdsLines) -> Result<TranscriptQc> {
+    let chromosome = &transcript.chromosome;
+    let strand = transcript.strand();
+    let oriented = |mut bases: Vec<u8>| {
+        if strand == Strand::Minus {
+            bases = dna::revcomp(&bases);
+        }
+        bases.make_ascii_uppercase();
+        bases
+    };
+
+    let mut motifs = Vec::new();
+    let exons = transcript.ascending_regions();
+    for pair in exons.windows(2) {
+        if pair[1].start - pair[0].end - 1 < 4 {
+            motifs.push(NO_MOTIF.to_string());
+            continue;
+        }
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        genome.fetch(chromosome, pair[0].end + 1, pair[0].end + 2, &mut left)?;
+        genome.fetch(chromosome, pair[1].start - 2, pair[1].start - 1, &mut right)?;
+        let (donor, acceptor) = match strand {
+            Strand::Plus => (oriented(left), oriented(right)),
+            Strand::Minus => (oriented(right), oriented(left)),
+        };
+        motifs.push(format!("{}-{}", String::from_utf8_lossy(&donor), String::from_utf8_lossy(&acceptor)));
+    }
+    if strand == Strand::Minus {
+        motifs.reverse();
+    }
+
+    let cds = match cds_lines.0.get(&transcript.id) {
+        Some(lines) => Some(check_cds(genome, chromosome, strand, lines)?),
+        None => None,
+    };
+    Ok(TranscriptQc { motifs, cds })
+}
+
+fn check_cds(genome: &dyn GenomeSource, chromosome: &str, strand: Strand, lines: &[(usize, usize, Option<u8>)]) -> Result<CdsQc> {
+    let mut segments = lines.to_vec();
+    match strand {
+        Strand::Plus => segments.sort_by_key(|s| s.0),
+        Strand::Minus => segments.sort_by_key(|s| std::cmp::Reverse(s.0)),
+    }
+
+    let mut sequence = Vec::new();
+    let mut phase_consistent = true;
+    let first_phase = segments[0].2.unwrap_or(0) as usize;
+    for &(start, end, phase) in &segments {
+        // Bases to skip to the next codon start, given the bases already coded
+        let expected = (3 - (sequence.len() + 3 - first_phase) % 3) % 3;
+        if phase.is_some_and(|p| p as usize != expected) {
+            phase_consistent = false;
+        }
+        let mut bases = Vec::with_capacity(end + 1 - start);
+        genome.fetch(chromosome, start, end, &mut bases)?;
+        if strand == Strand::Minus {
+            bases = dna::revcomp(&bases);
+        }
+        sequence.extend(bases);
+    }
+    sequence.make_ascii_uppercase();
+
+    let coding = sequence.get(first_phase..).unwrap_or_default();
+    let codons: Vec<&[u8]> = coding.chunks_exact(3).collect();
+    let is_stop = |codon: &[u8]| STOP_CODONS.contains(&codon);
+    Ok(CdsQc {
+        length: sequence.len(),
+        start_codon: first_phase == 0 && coding.starts_with(b"ATG"),
+        stop_codon: coding.len() % 3 == 0 && codons.last().is_some_and(|c| is_stop(c)),
+        internal_stops: codons.iter().take(codons.len().saturating_sub(1)).filter(|c| is_stop(c)).count(),
+        length_multiple_of_3: coding.len() % 3 == 0,
+        phase_consistent,
+    })
+}
+
+/// Run QC on every transcript, writing one row per transcript to a TSV and the failures
+/// to `errors`.
+pub fn write_qc_table(
+    genome: &dyn GenomeSource,
+    transcripts: &[Transcript],
+    cds_lines: &CdsLines,
+    out_path: &str,
+    errors: &mut Vec<Error>,
+    threads: usize,
+) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(out_path)?);
+    writeln!(
+        writer,
+        "transcript\tgene\tintrons\tnon_canonical\tmotifs\tcds_length\tstart_codon\tstop_codon\tinternal_stops\tlength_multiple_of_3\tphase_consistent"
+    )?;
+    let flag = |b: bool| if b { "yes" } else { "no" };
+    for_each_in_order(
+        transcripts,
+        threads,
+        |transcript| check_transcript(genome, transcript, cds_lines),
+        |transcript, qc| {
+            let qc = match qc {
+                Ok(qc) => qc,
+                Err(e) => {
+                    writeln!(writer, "{}\t{}{}", transcript.id, transcript.gene_id.as_deref().unwrap_or(""), "\tNA".repeat(9))?;
+                    let mut error = Error::warning(Code::QcSkipped, format!("Transcript {} is not checked: {}", transcript.id, e))
+                        .for_transcript(&transcript.id);
+                    if let Some(gene_id) = &transcript.gene_id {
+                        error = error.for_gene(gene_id);
+                    }
+                    errors.push(error);
+                    return Ok(());
+                }
+            };
+            write!(
+                writer,
+                "{}\t{}\t{}\t{}\t{}",
+                transcript.id,
+                transcript.gene_id.as_deref().unwrap_or(""),
+                qc.motifs.len(),
+                qc.non_canonical(),
+                qc.motifs.join(",")
+            )?;
+            match &qc.cds {
+                Some(cds) => writeln!(
+                    writer,
+                    "\t{}\t{}\t{}\t{}\t{}\t{}",
+                    cds.length,
+                    flag(cds.start_codon),
+                    flag(cds.stop_codon),
+                    cds.internal_stops,
+                    flag(cds.length_multiple_of_3),
+                    flag(cds.phase_consistent)
+                )?,
+                None => writeln!(writer, "\t\t\t\t\t\t")?,
+            }
+            errors.extend(qc.problems(transcript));
+            Ok(())
+        },
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gff3::parse_gff3_parallel;
+    use crate::transcript_builder::build_transcripts;
+    use std::io::Write;
+
+    fn transcript(strand: Strand, exons: &[(usize, usize)]) -> Transcript {
+        Transcript::test("t1", "chr1", strand, exons).gene("g1", None)
+    }
+
+    #[test]
+    fn test_splice_motifs_and_cds_checks() {
+        // Exon 1..6 = ATGAAA, intron 7..12 = GTCCAG, exon 13..18 = TGGTAA (ATG AAA TGG TAA)
+        let genome: HashMap<String, Vec<u8>> = [("chr1".to_string(), b"ATGAAAGTCCAGTGGTAA".to_vec())].into_iter().collect();
+        let plus = transcript(Strand::Plus, &[(1, 6), (13, 18)]);
+        let lines = CdsLines([("t1".to_string(), vec![(1, 6, Some(0)), (13, 18, Some(0))])].into_iter().collect());
+        let qc = check_transcript(&genome, &plus, &lines).unwrap();
+        assert_eq!(qc.motifs, ["GT-AG"]);
+        assert_eq!(
+            qc.cds,
+            Some(CdsQc { length: 12, start_codon: true, stop_codon: true, internal_stops: 0, length_multiple_of_3: true, phase_consistent: true })
+        );
+        assert!(qc.problems(&plus).is_empty());
+
+        // A wrong phase on the second segment, and a shorter first one that shifts the frame
+        let lines = CdsLines([("t1".to_string(), vec![(1, 5, Some(0)), (13, 18, Some(0))])].into_iter().collect());
+        let qc = check_transcript(&genome, &plus, &lines).unwrap();
+        let cds = qc.cds.as_ref().unwrap();
+        assert!(!cds.phase_consistent && !cds.length_multiple_of_3 && !cds.stop_codon);
+        let codes: Vec<Code> = qc.problems(&plus).iter().map(|e| e.code).collect();
+        assert_eq!(codes, [Code::MissingStopCodon, Code::CdsLength, Code::InconsistentPhase]);
+
+        // On the minus strand, the same intron reads CT-AC, which is not canonical
+        let minus = transcript(Strand::Minus, &[(1, 6), (13, 18)]);
+        let qc = check_transcript(&genome, &minus, &CdsLines::default()).unwrap();
+        assert_eq!(qc.motifs, ["CT-AC"]);
+        assert_eq!(qc.problems(&minus)[0].code, Code::NonCanonicalSpliceSite);
+
+        // A 2-base intron has no motif; a transcript on a sequence missing from the genome is skipped, not fatal
+        let short = transcript(Strand::Plus, &[(1, 6), (9, 18)]);
+        let mut missing = transcript(Strand::Plus, &[(1, 6), (13, 18)]);
+        missing.id = "t2".into();
+        missing.chromosome = "chr2".into();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("qc.tsv");
+        let mut errors = Vec::new();
+        write_qc_table(&genome, &[short, missing], &CdsLines::default(), path.to_str().unwrap(), &mut errors, 1).unwrap();
+        let table = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<&str> = table.lines().skip(1).collect();
+        assert_eq!(rows, ["t1\tg1\t1\t1\tNA\t\t\t\t\t\t", "t2\tg1\tNA\tNA\tNA\tNA\tNA\tNA\tNA\tNA\tNA"]);
+        let codes: Vec<(Code, Option<&str>)> = errors.iter().map(|e| (e.code, e.transcript_id.as_deref())).collect();
+        assert_eq!(codes, [(Code::NonCanonicalSpliceSite, Some("t1")), (Code::QcSkipped, Some("t2"))]);
+    }
+
+    #[test]
+    fn test_cds_of_renamed_transcript_is_checked() {
+        let genome: HashMap<String, Vec<u8>> = [("chr1".to_string(), b"ATGAAAGTCCAGTGGTAA".repeat(2))].into_iter().collect();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        for (gene, offset) in [("g1", 0), ("g2", 18)] {
+            writeln!(file, "chr1\t.\tmRNA\t{}\t{}\t.\t+\t.\tID=t1;Parent={}", offset + 1, offset + 18, gene).unwrap();
+            for (start, end) in [(1, 6), (13, 18)] {
+                writeln!(file, "chr1\t.\texon\t{}\t{}\t.\t+\t.\tParent=t1", offset + start, offset + end).unwrap();
+                writeln!(file, "chr1\t.\tCDS\t{}\t{}\t.\t+\t0\tParent=t1", offset + start, offset + end).unwrap();
+            }
+        }
+        let mut errors = Vec::new();
+        let annotation = parse_gff3_parallel(file.path().to_str().unwrap(), &["exon".to_string()], &mut errors, 1, true).unwrap();
+        let lines = CdsLines::new(&annotation);
+        let transcripts = build_transcripts(annotation, &mut errors);
+        let checked: Vec<(&str, bool)> = transcripts
+            .iter()
+            .map(|t| (t.id.as_str(), check_transcript(&genome, t, &lines).unwrap().cds.is_some_and(|cds| cds.stop_codon)))
+            .collect();
+        assert_eq!(checked, [("t1", true), ("t1_dup2", true)]);
+    }
+}
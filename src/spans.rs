@@ -0,0 +1,164 @@
+use crate::error::{Code, Error};
+use crate::structures::{Strand, TranscriptRegion};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Location of a gene, transcript or feature line.
+#[derive(Debug, Clone)]
+pub(crate) struct Span {
+    pub kind: &'static str,
+    pub chromosome: Arc<str>,
+    pub start: usize,
+    pub end: usize,
+    pub strand: Option<Strand>,
+    pub parent: Option<Arc<str>>,
+    pub line: usize,
+}
+
+/// The spans of the gene and transcript lines, and the hull of the exons of each
+/// transcript, collected while linking so that they can be checked against each other
+/// once every line has been seen.
+#[derive(Default)]
+pub(crate) struct SpanIndex {
+    spans: HashMap<Arc<str>, Span>,
+    transcripts: Vec<Arc<str>>, // in file order
+    exon_hulls: HashMap<Arc<str>, (usize, usize)>,
+}
+
+impl SpanIndex {
+    /// Add the span of a gene or transcript line.
+    pub(crate) fn add(&mut self, id: Arc<str>, span: Span) {
+        let transcript = span.kind == "transcript";
+        if self.spans.insert(id.clone(), span).is_none() && transcript {
+            self.transcripts.push(id);
+        }
+    }
+
+    pub(crate) fn add_exon(&mut self, transcript_id: Arc<str>, start: usize, end: usize) {
+        let hull = self.exon_hulls.entry(transcript_id).or_insert((start, end));
+        *hull = (hull.0.min(start), hull.1.max(end));
+    }
+
+    /// Report features outside the span of their transcript or on another seqid or
+    /// strand, transcripts outside their gene, and transcripts whose span differs from
+    /// the hull of their exons.
+    pub(crate) fn check(&self, features: &[&TranscriptRegion], path: &str, errors: &mut Vec<Error>) {
+        for feature in features {
+            if let Some(parent) = self.spans.get(&feature.transcript_id) {
+                let child = Span {
+                    kind: "feature",
+                    chromosome: feature.chromosome.clone(),
+                    start: feature.start,
+                    end: feature.end,
+                    strand: Some(feature.strand),
+                    parent: None,
+                    line: feature.line,
+                };
+                if let Some(error) = check_child(&feature.region_id, &child, &feature.transcript_id, parent) {
+                    errors.push(error.for_transcript(&*feature.transcript_id).for_feature(&feature.region_id).at(path, feature.line));
+                }
+            }
+        }
+
+        for id in &self.transcripts {
+            let transcript = &self.spans[id];
+            if let Some(gene_id) = &transcript.parent
+                && gene_id != id
+                && let Some(gene) = self.spans.get(gene_id)
+                && let Some(error) = check_child(id, transcript, gene_id, gene)
+            {
+                errors.push(error.for_transcript(&**id).for_gene(&**gene_id).at(path, transcript.line));
+            }
+            if let Some(&(start, end)) = self.exon_hulls.get(id)
+                && (start, end) != (transcript.start, transcript.end)
+            {
+                let mut error = Error::warning(
+                    Code::TranscriptSpanMismatch,
+                    format!(
+                        "Transcript {} spans {}..{} but its exons span {}..{}",
+                        id, transcript.start, transcript.end, start, end
+                    ),
+                )
+                .for_transcript(&**id);
+                if let Some(gene_id) = &transcript.parent {
+                    error = error.for_gene(&**gene_id);
+                }
+                errors.push(error.at(path, transcript.line));
+            }
+        }
+    }
+}
+
+/// The problem with a child outside its parent, if any.
+fn check_child(child_id: &str, child: &Span, parent_id: &str, parent: &Span) -> Option<Error> {
+    let mut kind = child.kind.to_string();
+    kind[..1].make_ascii_uppercase();
+    if child.chromosome != parent.chromosome {
+        return Some(Error::warning(
+            Code::ParentMismatch,
+            format!(
+                "{} {} is on {} but its {} {} (line {}) is on {}",
+                kind, child_id, child.chromosome, parent.kind, parent_id, parent.line, parent.chromosome
+            ),
+        ));
+    }
+    if let (Some(child_strand), Some(parent_strand)) = (child.strand, parent.strand)
+        && child_strand != parent_strand
+    {
+        return Some(Error::warning(
+            Code::ParentMismatch,
+            format!(
+                "{} {} is on strand {} but its {} {} (line {}) is on strand {}",
+                kind, child_id, child_strand, parent.kind, parent_id, parent.line, parent_strand
+            ),
+        ));
+    }
+    if child.start < parent.start || child.end > parent.end {
+        return Some(Error::warning(
+            Code::OutsideParent,
+            format!(
+                "{} {} ({}..{}) extends outside its {} {} ({}..{}, line {})",
+                kind, child_id, child.start, child.end, parent.kind, parent_id, parent.start, parent.end, parent.line
+            ),
+        ));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Code;
+    use crate::gff3::parse_gff3;
+    use std::io::Write;
+
+    #[test]
+    fn test_span_violations_are_reported() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(
+            file,
+            "chr1\t.\tgene\t100\t500\t.\t+\t.\tID=g1\n\
+             chr1\t.\tmRNA\t100\t400\t.\t+\t.\tID=ok;Parent=g1\n\
+             chr1\t.\texon\t100\t200\t.\t+\t.\tID=ok.1;Parent=ok\n\
+             chr1\t.\texon\t300\t400\t.\t+\t.\tID=ok.2;Parent=ok\n\
+             chr1\t.\tmRNA\t50\t400\t.\t+\t.\tID=wide;Parent=g1\n\
+             chr1\t.\texon\t100\t200\t.\t+\t.\tID=wide.1;Parent=wide\n\
+             chr1\t.\texon\t300\t450\t.\t-\t.\tID=wide.2;Parent=wide\n\
+             chr2\t.\texon\t300\t350\t.\t+\t.\tID=wide.3;Parent=wide\n"
+        )
+        .unwrap();
+        let mut errors = Vec::new();
+        parse_gff3(file.path().to_str().unwrap(), &["exon".to_string()], &mut errors).unwrap();
+        let found: Vec<(Code, Option<&str>, Option<usize>)> =
+            errors.iter().map(|e| (e.code, e.feature_id.as_deref().or(e.transcript_id.as_deref()), e.line)).collect();
+        assert_eq!(
+            found,
+            [
+                (Code::ParentMismatch, Some("wide.2"), Some(7)),
+                (Code::ParentMismatch, Some("wide.3"), Some(8)),
+                (Code::OutsideParent, Some("wide"), Some(5)),
+                (Code::TranscriptSpanMismatch, Some("wide"), Some(5)),
+            ]
+        );
+        assert!(errors[3].message.contains("its exons span 100..450"));
+    }
+}
@@ -0,0 +1,398 @@
+use crate::error::{json_string, Error};
+use crate::structures::{Annotation, Transcript};
+use anyhow::Result;
+use bio::io::fasta::{self, FastaRead};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// What the statistics need from the annotation, which `build_transcripts` consumes.
+#[derive(Debug, Default)]
+pub struct AnnotationFacts {
+    biotypes: HashMap<String, String>,
+    cds_lines: HashMap<String, usize>, // CDS lines per transcript ID
+}
+
+impl AnnotationFacts {
+    pub fn new(annotation: &Annotation) -> Self {
+        let mut cds_lines: HashMap<String, usize> = HashMap::new();
+        for cds in &annotation.cds {
+            *cds_lines.entry(cds.transcript_id.to_string()).or_default() += 1;
+        }
+        AnnotationFacts { biotypes: annotation.biotypes.clone(), cds_lines }
+    }
+}
+
+/// Genes, transcripts, exons (extracted features) and CDS lines of a group of transcripts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counts {
+    pub genes: usize,
+    pub transcripts: usize,
+    pub exons: usize,
+    pub cds: usize,
+}
+
+/// Minimum, maximum, mean, median and N50 of a set of lengths.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct LengthSummary {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: usize,
+    pub n50: usize,
+}
+
+impl LengthSummary {
+    pub fn new(lengths: &[usize]) -> Self {
+        if lengths.is_empty() {
+            return LengthSummary::default();
+        }
+        let mut sorted = lengths.to_vec();
+        sorted.sort_unstable();
+        let total: usize = sorted.iter().sum();
+        // N50: the length of the sequence at which half of all bases are reached,
+        // counting from the longest
+        let mut covered = 0;
+        let n50 = sorted
+            .iter()
+            .rev()
+            .find(|&&l| {
+                covered += l;
+                covered * 2 >= total
+            })
+            .copied()
+            .unwrap_or(0);
+        LengthSummary {
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: total as f64 / sorted.len() as f64,
+            median: sorted[sorted.len() / 2],
+            n50,
+        }
+    }
+}
+
+/// Upper bounds (exclusive) of the transcript length bins; the last bin is open.
+const LENGTH_BINS: [usize; 6] = [200, 500, 1000, 2000, 5000, 10_000];
+
+/// Exon counts and isoforms per gene from this value on share one bin.
+const COUNT_BINS: usize = 10;
+
+/// Statistics of the extracted transcripts, and of the rejected ones by reason.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub total: Counts,
+    pub by_biotype: BTreeMap<String, Counts>,
+    pub by_chromosome: BTreeMap<String, Counts>,
+    pub lengths: LengthSummary,
+    pub length_bins: Vec<(String, usize)>,
+    pub exon_counts: Vec<(String, usize)>,
+    pub isoforms: Vec<(String, usize)>,
+    /// Fraction of G and C among the A, C, G and T bases of the extracted sequences.
+    pub gc: Option<f64>,
+    /// Rejected transcripts by the code of their first problem.
+    pub rejected: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    /// Collect the statistics of the extracted transcripts. A transcript that has problems
+    /// but was not extracted counts as rejected.
+    pub fn collect(facts: &AnnotationFacts, transcripts: &[Transcript], errors: &[Error]) -> Self {
+        let mut stats = Stats::default();
+        let biotype_of = |id: &str| facts.biotypes.get(id).map(|b| b.as_str());
+
+        // Genes in order of their first transcript, with its biotype and chromosome
+        let mut genes: Vec<(&str, &str, &str)> = Vec::new();
+        let mut isoforms: HashMap<&str, usize> = HashMap::new();
+        let mut lengths = Vec::with_capacity(transcripts.len());
+        let mut exon_counts = vec![0; COUNT_BINS];
+        for t in transcripts {
+            let gene = t.gene_id.as_deref().unwrap_or(&t.id);
+            let biotype = biotype_of(&t.id).or_else(|| biotype_of(gene)).unwrap_or("unknown");
+            let isoform_count = isoforms.entry(gene).or_default();
+            if *isoform_count == 0 {
+                genes.push((gene, biotype_of(gene).unwrap_or(biotype), &t.chromosome));
+            }
+            *isoform_count += 1;
+
+            let cds = facts.cds_lines.get(&t.id).copied().unwrap_or(0);
+            for group in [
+                &mut stats.total,
+                stats.by_biotype.entry(biotype.to_string()).or_default(),
+                stats.by_chromosome.entry(t.chromosome.clone()).or_default(),
+            ] {
+                group.transcripts += 1;
+                group.exons += t.regions.len();
+                group.cds += cds;
+            }
+            lengths.push(t.size());
+            exon_counts[t.regions.len().min(COUNT_BINS) - 1] += 1;
+        }
+        for (_, biotype, chromosome) in &genes {
+            stats.total.genes += 1;
+            stats.by_biotype.entry(biotype.to_string()).or_default().genes += 1;
+            stats.by_chromosome.entry(chromosome.to_string()).or_default().genes += 1;
+        }
+
+        stats.lengths = LengthSummary::new(&lengths);
+        let mut length_bins = vec![0; LENGTH_BINS.len() + 1];
+        for &l in &lengths {
+            length_bins[LENGTH_BINS.iter().position(|&bound| l < bound).unwrap_or(LENGTH_BINS.len())] += 1;
+        }
+        stats.length_bins = length_bins
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let label = match i {
+                    0 => format!("<{}", LENGTH_BINS[0]),
+                    i if i == LENGTH_BINS.len() => format!(">={}", LENGTH_BINS[i - 1]),
+                    i => format!("{}-{}", LENGTH_BINS[i - 1], LENGTH_BINS[i] - 1),
+                };
+                (label, count)
+            })
+            .collect();
+        stats.exon_counts = count_bins(exon_counts);
+        let mut isoform_counts = vec![0; COUNT_BINS];
+        for (gene, _, _) in &genes {
+            isoform_counts[isoforms[gene].min(COUNT_BINS) - 1] += 1;
+        }
+        stats.isoforms = count_bins(isoform_counts);
+
+        let extracted: HashSet<&str> = transcripts.iter().map(|t| t.id.as_str()).collect();
+        let mut seen = HashSet::new();
+        for e in errors {
+            if let Some(id) = &e.transcript_id
+                && e.rejects_transcript()
+                && !extracted.contains(id.as_str())
+                && seen.insert(id.as_str())
+            {
+                *stats.rejected.entry(e.code.to_string()).or_default() += 1;
+            }
+        }
+        stats
+    }
+
+    /// Take the GC content from the sequences of a FASTA, such as the written transcriptome.
+    pub fn read_gc(&mut self, fasta_path: &str) -> Result<()> {
+        let mut reader = fasta::Reader::from_file(fasta_path)?;
+        let mut record = fasta::Record::new();
+        let (mut gc, mut acgt) = (0u64, 0u64);
+        loop {
+            reader.read(&mut record)?;
+            if record.is_empty() {
+                break;
+            }
+            for b in record.seq() {
+                match b.to_ascii_uppercase() {
+                    b'G' | b'C' => {
+                        gc += 1;
+                        acgt += 1;
+                    }
+                    b'A' | b'T' => acgt += 1,
+                    _ => (),
+                }
+            }
+        }
+        self.gc = (acgt > 0).then(|| gc as f64 / acgt as f64);
+        Ok(())
+    }
+
+    pub fn write_text(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let t = &self.total;
+        writeln!(out, "Genes\t{}\nTranscripts\t{}\nExons\t{}\nCDS\t{}", t.genes, t.transcripts, t.exons, t.cds)?;
+        let l = &self.lengths;
+        writeln!(out, "Transcript length\tmin {}\tmedian {}\tmean {:.1}\tmax {}\tN50 {}", l.min, l.median, l.mean, l.max, l.n50)?;
+        if let Some(gc) = self.gc {
+            writeln!(out, "GC content\t{:.2}%", gc * 100.0)?;
+        }
+        for (title, groups) in [("Biotype", &self.by_biotype), ("Chromosome", &self.by_chromosome)] {
+            writeln!(out, "\n{}\tgenes\ttranscripts\texons\tcds", title)?;
+            for (name, c) in groups {
+                writeln!(out, "{}\t{}\t{}\t{}\t{}", name, c.genes, c.transcripts, c.exons, c.cds)?;
+            }
+        }
+        for (title, bins) in [
+            ("Transcript length", &self.length_bins),
+            ("Exons per transcript", &self.exon_counts),
+            ("Isoforms per gene", &self.isoforms),
+        ] {
+            writeln!(out, "\n{}\tcount", title)?;
+            for (label, count) in bins {
+                writeln!(out, "{}\t{}", label, count)?;
+            }
+        }
+        writeln!(out, "\nRejected transcripts\tcount")?;
+        for (code, count) in &self.rejected {
+            writeln!(out, "{}\t{}", code, count)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_json(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let counts = |c: &Counts| {
+            format!("{{\"genes\":{},\"transcripts\":{},\"exons\":{},\"cds\":{}}}", c.genes, c.transcripts, c.exons, c.cds)
+        };
+        let groups = |groups: &BTreeMap<String, Counts>| {
+            let fields: Vec<String> = groups.iter().map(|(k, c)| format!("{}:{}", json_string(k), counts(c))).collect();
+            format!("{{{}}}", fields.join(","))
+        };
+        let bins = |bins: &[(String, usize)]| {
+            let fields: Vec<String> = bins.iter().map(|(k, n)| format!("{}:{}", json_string(k), n)).collect();
+            format!("{{{}}}", fields.join(","))
+        };
+        let l = &self.lengths;
+        let rejected: Vec<(String, usize)> = self.rejected.iter().map(|(k, &n)| (k.clone(), n)).collect();
+        writeln!(
+            out,
+            "{{\"total\":{},\"by_biotype\":{},\"by_chromosome\":{},\
+             \"length\":{{\"min\":{},\"median\":{},\"mean\":{:.1},\"max\":{},\"n50\":{}}},\
+             \"length_bins\":{},\"exons_per_transcript\":{},\"isoforms_per_gene\":{},\"gc\":{},\"rejected\":{}}}",
+            counts(&self.total),
+            groups(&self.by_biotype),
+            groups(&self.by_chromosome),
+            l.min,
+            l.median,
+            l.mean,
+            l.max,
+            l.n50,
+            bins(&self.length_bins),
+            bins(&self.exon_counts),
+            bins(&self.isoforms),
+            self.gc.map_or("null".to_string(), |gc| format!("{:.4}", gc)),
+            bins(&rejected),
+        )
+    }
+
+    /// A self-contained HTML page with the tables and bar charts of the distributions.
+    pub fn write_html(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>thaf statistics</title><style>\n\
+             body {{ font-family: sans-serif; margin: 2em; }} table {{ border-collapse: collapse; margin-bottom: 2em; }}\n\
+             td, th {{ border: 1px solid #ccc; padding: 2px 8px; text-align: right; }} th:first-child, td:first-child {{ text-align: left; }}\n\
+             .bar {{ background: #4a7ab5; height: 1em; }}\n</style></head><body>\n<h1>Transcriptome statistics</h1>"
+        )?;
+        let t = &self.total;
+        let l = &self.lengths;
+        writeln!(out, "<table>")?;
+        for (name, value) in [
+            ("Genes", t.genes.to_string()),
+            ("Transcripts", t.transcripts.to_string()),
+            ("Exons", t.exons.to_string()),
+            ("CDS", t.cds.to_string()),
+            ("Transcript length (min / median / mean / max)", format!("{} / {} / {:.1} / {}", l.min, l.median, l.mean, l.max)),
+            ("N50", l.n50.to_string()),
+            ("GC content", self.gc.map_or("-".to_string(), |gc| format!("{:.2}%", gc * 100.0))),
+        ] {
+            writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", name, value)?;
+        }
+        writeln!(out, "</table>")?;
+
+        for (title, groups) in [("Biotype", &self.by_biotype), ("Chromosome", &self.by_chromosome)] {
+            writeln!(out, "<h2>By {}</h2>\n<table><tr><th>{}</th><th>Genes</th><th>Transcripts</th><th>Exons</th><th>CDS</th></tr>", title.to_lowercase(), title)?;
+            for (name, c) in groups {
+                writeln!(out, "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>", html_escape(name), c.genes, c.transcripts, c.exons, c.cds)?;
+            }
+            writeln!(out, "</table>")?;
+        }
+
+        let rejected: Vec<(String, usize)> = self.rejected.iter().map(|(k, &n)| (k.clone(), n)).collect();
+        for (title, bins) in [
+            ("Transcript length", self.length_bins.as_slice()),
+            ("Exons per transcript", self.exon_counts.as_slice()),
+            ("Isoforms per gene", self.isoforms.as_slice()),
+            ("Rejected transcripts", rejected.as_slice()),
+        ] {
+            let max = bins.iter().map(|(_, n)| *n).max().unwrap_or(0).max(1);
+            writeln!(out, "<h2>{}</h2>\n<table>", title)?;
+            for (label, count) in bins {
+                writeln!(
+                    out,
+                    "<tr><td>{}</td><td>{}</td><td style=\"width:300px\"><div class=\"bar\" style=\"width:{:.1}%\"></div></td></tr>",
+                    html_escape(label),
+                    count,
+                    *count as f64 * 100.0 / max as f64
+                )?;
+            }
+            writeln!(out, "</table>")?;
+        }
+        writeln!(out, "</body></html>")
+    }
+}
+
+/// Label counts of 1, 2, ... with the last bin open.
+fn count_bins(counts: Vec<usize>) -> Vec<(String, usize)> {
+    let last = counts.len();
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, n)| (if i + 1 == last { format!("{}+", i + 1) } else { (i + 1).to_string() }, n))
+        .collect()
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Write the statistics to each given file: text, JSON and HTML.
+pub fn write_stats(stats: &Stats, text: Option<&str>, json: Option<&str>, html: Option<&str>) -> Result<()> {
+    type Writer = fn(&Stats, &mut BufWriter<File>) -> std::io::Result<()>;
+    let writers: [(Option<&str>, Writer); 3] = [
+        (text, |s, out| s.write_text(out)),
+        (json, |s, out| s.write_json(out)),
+        (html, |s, out| s.write_html(out)),
+    ];
+    for (path, write) in writers {
+        if let Some(path) = path {
+            let mut out = BufWriter::new(File::create(path)?);
+            write(stats, &mut out)?;
+            out.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Code;
+    use crate::structures::Strand;
+
+    #[test]
+    fn test_collect_counts_distributions_and_rejections() {
+        let facts = AnnotationFacts {
+            biotypes: [("g1", "protein_coding"), ("t3", "lncRNA")].into_iter().map(|(k, v)| (k.into(), v.into())).collect(),
+            cds_lines: [("t1".to_string(), 2)].into_iter().collect(),
+        };
+        let transcripts = vec![
+            Transcript::test("t1", "chr1", Strand::Plus, &[(1, 100), (201, 300)]).gene("g1", None),
+            Transcript::test("t2", "chr1", Strand::Plus, &[(1, 100), (201, 250), (301, 400)]).gene("g1", None),
+            Transcript::test("t3", "chr2", Strand::Plus, &[(1, 500), (601, 1150)]).gene("g2", None),
+        ];
+        let errors = vec![
+            Error::fatal(Code::OverlappingRegions, "overlap").for_transcript("t4"),
+            Error::fatal(Code::MixedStrand, "mixed").for_transcript("t4"),
+            // Span problems are reported while parsing, before the transcript is rejected
+            Error::warning(Code::TranscriptSpanMismatch, "span").for_transcript("t5"),
+            Error::warning(Code::SingleRegion, "single").for_transcript("t5"),
+            Error::warning(Code::ShortRegion, "short").for_transcript("t1"),
+        ];
+
+        let stats = Stats::collect(&facts, &transcripts, &errors);
+        assert_eq!(stats.total, Counts { genes: 2, transcripts: 3, exons: 7, cds: 2 });
+        assert_eq!(stats.by_biotype["protein_coding"], Counts { genes: 1, transcripts: 2, exons: 5, cds: 2 });
+        assert_eq!(stats.by_biotype["lncRNA"], Counts { genes: 1, transcripts: 1, exons: 2, cds: 0 });
+        assert_eq!(stats.by_chromosome["chr2"].genes, 1);
+        assert_eq!(stats.lengths, LengthSummary { min: 200, max: 1050, mean: 500.0, median: 250, n50: 1050 });
+        assert_eq!(stats.exon_counts[1..3], [("2".to_string(), 2), ("3".to_string(), 1)]);
+        assert_eq!(stats.isoforms[0..2], [("1".to_string(), 1), ("2".to_string(), 1)]);
+        assert_eq!(stats.length_bins[1], ("200-499".to_string(), 2));
+        assert_eq!(stats.rejected.iter().collect::<Vec<_>>(), [(&"OVERLAPPING_REGIONS".to_string(), &1), (&"SINGLE_REGION".to_string(), &1)]);
+
+        let mut json = Vec::new();
+        stats.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with("{\"total\":{\"genes\":2,\"transcripts\":3,\"exons\":7,\"cds\":2},"));
+        assert!(json.contains("\"n50\":1050"));
+    }
+}
@@ -0,0 +1,230 @@
+use crate::duplicates::SequenceNames;
+use crate::error::{Code, Error};
+use crate::genome::{missing_chromosome, out_of_range, GenomeSource};
+use crate::parallel::map_in_order;
+use crate::structures::Transcript;
+use crate::transcript_builder::extract_transcript_sequence;
+use crate::variants::{create_variant_table, extract_personal_sequence, write_variant_rows, Variant, VariantSet};
+use anyhow::Result;
+use bio::io::fasta::{self, FastaRead};
+use flate2::read::MultiGzDecoder;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+/// Order of the records written when the genome is streamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputOrder {
+    /// The order of the transcript models, as in the BED, GTF and other outputs.
+    /// Extracted sequences are kept until the whole genome has been read.
+    Annotation,
+    /// Chromosome by chromosome, in the order of the genome FASTA. Sequences are
+    /// written as soon as their chromosome has been read.
+    Genome,
+}
+
+/// A genome FASTA read from start to end, with the names given to its records.
+pub struct GenomeStream<R: BufRead> {
+    reader: R,
+    names: SequenceNames,
+}
+
+impl<R: BufRead> GenomeStream<R> {
+    /// Repeated sequence names are reported, and renamed if `rename_duplicates` is set, as by
+    /// `open_genome_with`.
+    pub fn new(reader: R, fasta_path: &str, rename_duplicates: bool) -> Self {
+        GenomeStream { reader, names: SequenceNames::new(fasta_path, rename_duplicates) }
+    }
+}
+
+/// Open a genome FASTA for reading from start to end: `-` is the standard input, and
+/// gzip (including BGZF) content is decompressed.
+pub fn open_fasta_stream(fasta_path: &str, rename_duplicates: bool) -> Result<GenomeStream<Box<dyn BufRead>>> {
+    let mut reader: Box<dyn BufRead> = if fasta_path == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(fasta_path)?))
+    };
+    if reader.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        reader = Box::new(BufReader::new(MultiGzDecoder::new(reader)));
+    }
+    let name = if fasta_path == "-" { "the standard input" } else { fasta_path };
+    Ok(GenomeStream::new(reader, name, rename_duplicates))
+}
+
+/// Whether a genome FASTA can only be read from start to end: the standard input, or
+/// gzip content without a `.fai` index.
+pub fn needs_streaming(fasta_path: &str) -> Result<bool> {
+    if fasta_path == "-" {
+        return Ok(true);
+    }
+    if Path::new(&format!("{}.fai", fasta_path)).exists() {
+        return Ok(false);
+    }
+    let mut magic = [0u8; 2];
+    let read = File::open(fasta_path)?.read(&mut magic)?;
+    Ok(read == 2 && magic == [0x1f, 0x8b])
+}
+
+/// Read a genome FASTA one record at a time and call `visit` with every record that has
+/// transcripts on it, as a genome of that single sequence. Each record is replaced by the
+/// next one, so memory is bounded by the largest chromosome. Chromosomes of the
+/// transcripts that are not in the FASTA are reported as fatal errors.
+pub fn for_each_chromosome<'a>(
+    stream: GenomeStream<impl BufRead>,
+    transcripts: &'a [Transcript],
+    errors: &mut Vec<Error>,
+    mut visit: impl FnMut(&dyn GenomeSource, &[(usize, &'a Transcript)], &mut Vec<Error>) -> Result<()>,
+) -> Result<()> {
+    let mut by_chromosome: HashMap<&str, Vec<(usize, &Transcript)>> = HashMap::new();
+    for (i, transcript) in transcripts.iter().enumerate() {
+        by_chromosome.entry(transcript.chromosome.as_str()).or_default().push((i, transcript));
+    }
+
+    let GenomeStream { reader, mut names } = stream;
+    let mut reader = fasta::Reader::from_bufread(reader);
+    let mut record = fasta::Record::new();
+    loop {
+        reader.read(&mut record)?;
+        if record.is_empty() {
+            break;
+        }
+        let Some(name) = names.name(record.id()) else {
+            continue;
+        };
+        let Some(on_record) = by_chromosome.remove(name.as_str()) else {
+            continue;
+        };
+        visit(&SingleSequence { name: &name, record: &record }, &on_record, errors)?;
+    }
+    names.finish(errors);
+
+    let mut missing: Vec<_> = by_chromosome.into_iter().collect();
+    missing.sort_by_key(|(_, on_record)| on_record[0].0);
+    for (chromosome, on_record) in missing {
+        errors.push(Error::fatal(Code::MissingChromosome, format!(
+            "{} {} transcript(s) were not extracted.",
+            missing_chromosome(chromosome),
+            on_record.len()
+        )));
+    }
+    Ok(())
+}
+
+/// The record currently read from a streamed genome, under the name given to it.
+struct SingleSequence<'a> {
+    name: &'a str,
+    record: &'a fasta::Record,
+}
+
+impl GenomeSource for SingleSequence<'_> {
+    fn fetch(&self, chromosome: &str, start: usize, end: usize, out: &mut Vec<u8>) -> Result<()> {
+        if chromosome != self.name {
+            return Err(missing_chromosome(chromosome));
+        }
+        let bases = start
+            .checked_sub(1)
+            .and_then(|s| self.record.seq().get(s..end))
+            .ok_or_else(|| out_of_range(chromosome, start, end))?;
+        out.extend_from_slice(bases);
+        Ok(())
+    }
+
+    fn length(&self, chromosome: &str) -> Option<usize> {
+        (chromosome == self.name).then(|| self.record.seq().len())
+    }
+}
+
+/// Extract transcript sequences while streaming the genome, and write them in the requested
+/// order. With `personal`, the variants are applied and optionally listed in a TSV table. Records are the same as those of `write_transcriptome`
+/// and `write_personal_transcriptome`.
+pub fn stream_transcriptome(
+    stream: GenomeStream<impl BufRead>,
+    transcripts: &[Transcript],
+    output_fasta_path: &str,
+    order: OutputOrder,
+    personal: Option<(&VariantSet, Option<&str>)>,
+    errors: &mut Vec<Error>,
+    threads: usize,
+) -> Result<()> {
+    let mut writer = fasta::Writer::to_file(output_fasta_path)?;
+    let variants = personal.map(|(variants, _)| variants);
+    let mut table = personal.and_then(|(_, path)| path).map(create_variant_table).transpose()?;
+    let mut kept: Vec<Option<(Vec<u8>, Vec<&Variant>)>> = Vec::new();
+    if order == OutputOrder::Annotation {
+        kept.resize_with(transcripts.len(), || None);
+    }
+
+    let mut write = |transcript: &Transcript, seq: &[u8], applied: &[&Variant]| -> Result<()> {
+        let description = (!applied.is_empty()).then(|| format!("variants={}", applied.len()));
+        writer.write(&transcript.id, description.as_deref(), seq)?;
+        if let Some(table) = table.as_mut() {
+            write_variant_rows(table, transcript, applied)?;
+        }
+        Ok(())
+    };
+
+    for_each_chromosome(stream, transcripts, errors, |genome, on_record, errors| {
+        let extracted = map_in_order(on_record, threads, |&(_, transcript)| {
+            let mut problems = Vec::new();
+            let extracted = match variants {
+                Some(variants) => extract_personal_sequence(genome, transcript, variants, &mut problems),
+                None => extract_transcript_sequence(genome, transcript).map(|seq| (seq, Vec::new())),
+            };
+            (extracted, problems)
+        });
+        for (&(i, transcript), (extracted, problems)) in on_record.iter().zip(extracted) {
+            errors.extend(problems);
+            let (seq, applied) = extracted?;
+            match order {
+                OutputOrder::Annotation => kept[i] = Some((seq, applied)),
+                OutputOrder::Genome => write(transcript, &seq, &applied)?,
+            }
+        }
+        Ok(())
+    })?;
+
+    for (transcript, extracted) in transcripts.iter().zip(kept) {
+        if let Some((seq, applied)) = extracted {
+            write(transcript, &seq, &applied)?;
+        }
+    }
+
+    writer.flush()?;
+    if let Some(table) = table.as_mut() {
+        std::io::Write::flush(table)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Severity;
+    use crate::structures::Strand;
+
+    #[test]
+    fn test_stream_transcriptome_order_and_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("t.fa");
+        let genome = b">chr1\nACGTACGT\n>chr2\nTTTTGGGG\n";
+        let transcripts = vec![
+            Transcript::test("b", "chr2", Strand::Plus, &[(4, 6)]),
+            Transcript::test("x", "chr3", Strand::Plus, &[(1, 2)]),
+            Transcript::test("a", "chr1", Strand::Plus, &[(2, 3)]),
+        ];
+
+        let read = |order| {
+            let mut errors = Vec::new();
+            stream_transcriptome(GenomeStream::new(&genome[..], "g.fa", false), &transcripts, out.to_str().unwrap(), order, None, &mut errors, 3)
+                .unwrap();
+            assert_eq!(errors.len(), 1);
+            assert!(matches!(errors[0].severity, Severity::Fatal));
+            assert!(errors[0].message.contains("'chr3'"));
+            std::fs::read_to_string(&out).unwrap()
+        };
+        assert_eq!(read(OutputOrder::Annotation), ">b\nTGG\n>a\nCG\n");
+        assert_eq!(read(OutputOrder::Genome), ">a\nCG\n>b\nTGG\n");
+    }
+}
@@ -1,21 +1,19 @@
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::fmt;
-use crate::error::Error;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Strand {
     Plus,
     Minus,
 }
 
 impl Strand {
-    pub fn from_char(x: char, errors: &mut Vec<Error>) -> Option<Strand> {
+    pub fn from_char(x: char) -> Option<Strand> {
         match x {
             '+' => Some(Strand::Plus),
             '-' => Some(Strand::Minus),
-            _ => {
-                errors.push(Error::fatal(format!("Invalid strand [{x}]")));
-                None
-            }
+            _ => None,
         }
     }
 }
@@ -37,11 +35,26 @@ pub struct Region {
     pub strand: Strand,
 }
 
+/// Name of one of `regions`, in the order given. Regions sharing an ID are the segments of
+/// one discontinuous feature, such as a CDS written on several lines; they are named
+/// `{id}_seg{n}`, numbered from 1.
+pub fn region_label(regions: &[Region], region: &Region) -> String {
+    let segments: Vec<&Region> = regions.iter().filter(|r| r.id == region.id).collect();
+    if segments.len() < 2 {
+        return region.id.clone();
+    }
+    let n = segments.iter().position(|r| (r.start, r.end) == (region.start, region.end)).unwrap_or(0) + 1;
+    format!("{}_seg{}", region.id, n)
+}
+
 #[derive(Debug, Clone)]
 pub struct Transcript {
     pub id: String,
     pub chromosome: String, // added chromosome field
     pub regions: Vec<Region>,
+    pub gene_id: Option<String>,
+    pub gene_name: Option<String>,
+    pub cds: Option<(usize, usize)>, // genomic span of the coding sequence, if annotated
 }
 
 impl Transcript {
@@ -49,15 +62,141 @@ impl Transcript {
     pub fn size(&self) -> usize {
         self.regions.iter().map(|r| r.end - r.start + 1).sum()
     }
+
+    pub fn strand(&self) -> Strand {
+        self.regions[0].strand
+    }
+
+    /// Leftmost genomic position (1-based, inclusive) covered by the transcript.
+    pub fn start(&self) -> usize {
+        self.regions.iter().map(|r| r.start).min().unwrap_or(0)
+    }
+
+    /// Rightmost genomic position (1-based, inclusive) covered by the transcript.
+    pub fn end(&self) -> usize {
+        self.regions.iter().map(|r| r.end).max().unwrap_or(0)
+    }
+
+    /// Name of a region of the transcript in messages and per-feature exports (see
+    /// `region_label`).
+    pub fn region_label(&self, region: &Region) -> String {
+        region_label(&self.regions, region)
+    }
+
+    /// Regions in ascending genomic order, regardless of strand.
+    pub fn ascending_regions(&self) -> Vec<&Region> {
+        let mut regions: Vec<&Region> = self.regions.iter().collect();
+        regions.sort_by_key(|r| r.start);
+        regions
+    }
+
+    /// 0-based offset of a genomic position within the spliced transcript,
+    /// or None if the position is not covered by any region.
+    pub fn transcript_offset(&self, pos: usize) -> Option<usize> {
+        let mut offset = 0;
+        for region in &self.regions {
+            if pos >= region.start && pos <= region.end {
+                return Some(offset + match region.strand {
+                    Strand::Plus => pos - region.start,
+                    Strand::Minus => region.end - pos,
+                });
+            }
+            offset += region.end - region.start + 1;
+        }
+        None
+    }
+
+    /// Genomic segments covering the transcript offsets `from..to` (0-based, half-open),
+    /// in transcript order. Each item is (region index, start, end), 1-based inclusive.
+    pub fn genomic_segments(&self, from: usize, to: usize) -> Vec<(usize, usize, usize)> {
+        let mut segments = Vec::new();
+        let mut offset = 0;
+        for (index, region) in self.regions.iter().enumerate() {
+            let length = region.end - region.start + 1;
+            let lo = from.max(offset) - offset;
+            let hi = to.min(offset + length).saturating_sub(offset);
+            if lo < hi {
+                segments.push(match region.strand {
+                    Strand::Plus => (index, region.start + lo, region.start + hi - 1),
+                    Strand::Minus => (index, region.end + 1 - hi, region.end - lo),
+                });
+            }
+            offset += length;
+        }
+        segments
+    }
+
+    /// The CDS span as transcript offsets (0-based, half-open), if both ends fall within regions.
+    pub fn cds_offsets(&self) -> Option<(usize, usize)> {
+        let (start, end) = self.cds?;
+        let a = self.transcript_offset(start)?;
+        let b = self.transcript_offset(end)?;
+        Some((a.min(b), a.max(b) + 1))
+    }
+}
+
+/// Transcripts for the tests of other modules.
+#[cfg(test)]
+impl Transcript {
+    /// A transcript whose regions `e1`, `e2`, ... are `regions`, given in transcript order.
+    pub(crate) fn test(id: &str, chromosome: &str, strand: Strand, regions: &[(usize, usize)]) -> Self {
+        Transcript {
+            id: id.into(),
+            chromosome: chromosome.into(),
+            regions: regions
+                .iter()
+                .enumerate()
+                .map(|(i, &(start, end))| Region { id: format!("e{}", i + 1), start, end, strand })
+                .collect(),
+            gene_id: None,
+            gene_name: None,
+            cds: None,
+        }
+    }
+
+    /// Rename the regions, in transcript order.
+    pub(crate) fn region_ids(mut self, ids: &[&str]) -> Self {
+        for (region, id) in self.regions.iter_mut().zip(ids) {
+            region.id = id.to_string();
+        }
+        self
+    }
+
+    pub(crate) fn gene(mut self, gene_id: &str, gene_name: Option<&str>) -> Self {
+        self.gene_id = Some(gene_id.into());
+        self.gene_name = gene_name.map(String::from);
+        self
+    }
+
+    pub(crate) fn coding(mut self, cds: Option<(usize, usize)>) -> Self {
+        self.cds = cds;
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct TranscriptRegion {
-    pub chromosome: String,
+    pub chromosome: Arc<str>,
     pub start: usize,
     pub end: usize,
     pub strand: Strand,
-    pub transcript_id: String,
+    pub transcript_id: Arc<str>,
     pub region_id: String,
-    pub gene_id: Option<String>,
+    pub gene_id: Option<Arc<str>>,
+    pub phase: Option<u8>, // GFF3 phase of CDS features
+    pub line: usize, // 1-based line of the feature in its source file
+}
+
+/// Parsed GFF3 content: regions of the requested features, plus the CDS lines
+/// used to annotate the coding span of each transcript.
+#[derive(Debug, Clone, Default)]
+pub struct Annotation {
+    pub regions: Vec<TranscriptRegion>,
+    pub cds: Vec<TranscriptRegion>,
+    pub gene_names: HashMap<String, String>, // gene ID to its Name attribute
+    pub biotypes: HashMap<String, String>, // gene or transcript ID to its biotype
+    pub source: String, // path of the parsed file
+    /// ID and Parent given to a line where they differ from the file, by line: renamed
+    /// duplicates, the children of renamed parents, and synthesized feature IDs.
+    pub renamed: HashMap<usize, (Option<String>, Option<String>)>,
 }
@@ -1,51 +1,57 @@
-pub(crate) use crate::structures::{Region, Strand, Transcript, TranscriptRegion};
+pub(crate) use crate::structures::{region_label, Annotation, Region, Strand, Transcript, TranscriptRegion};
 use anyhow::Result;
 use bio::alphabets::dna;
 use bio::data_structures::interval_tree::IntervalTree;
 use bio::io::fasta;
 use std::collections::HashMap;
-use crate::error::Error;
+use std::sync::Arc;
+use crate::error::{Code, Error};
+use crate::parallel::for_each_in_order;
+use crate::duplicates::SequenceNames;
+use crate::genome::{open_genome, GenomeSource};
 
 impl Transcript {
+    #[allow(clippy::int_plus_one)] // reads as "length <= 3"
     pub fn new(id: String, chromosome: String, mut regions: Vec<Region>, errors: &mut Vec<Error>) -> Option<Self> {
         if regions.is_empty() {
-            errors.push(Error::fatal(format!("Transcript {} has no regions.", id)));
+            errors.push(Error::fatal(Code::NoRegions, format!("Transcript {} has no regions.", id)).for_transcript(&id));
             return None;
         }
 
         // Verify all regions have the same strand
         let first_strand = regions[0].strand;
         if regions.iter().any(|r| r.strand != first_strand) {
-            errors.push(Error::fatal(format!("Transcript {} has mixed strands.", id)));
+            errors.push(Error::fatal(Code::MixedStrand, format!("Transcript {} has mixed strands.", id)).for_transcript(&id));
             return None;
         }
 
         // Sort regions depending on the strand
         match first_strand {
             Strand::Plus => regions.sort_by_key(|r| r.start),
-            Strand::Minus => regions.sort_by(|a, b| b.start.cmp(&a.start)),
+            Strand::Minus => regions.sort_by_key(|r| std::cmp::Reverse(r.start)),
         }
 
         // Check for overlapping regions
         let mut interval_tree: IntervalTree<usize, &Region> = IntervalTree::new();
+        let label = |region: &Region| region_label(&regions, region);
 
         for region in &regions {
             if region.start > region.end {
-                errors.push(Error::fatal(format!("Negative width region {}..{}, region {} strand {}", region.start, region.end, region.id, region.strand)));
+                errors.push(Error::fatal(Code::NegativeWidth, format!("Negative width region {}..{}, region {} strand {}", region.start, region.end, label(region), region.strand)).for_transcript(&id).for_feature(&region.id));
                 return None;
             } else if region.end - region.start + 1 <= 3 {
-                errors.push(Error::warning(format!("Suspicious: {} is only {} nucleoptide length: {} .. {}", region.id, region.end - region.start + 1, region.start, region.end)));
+                errors.push(Error::warning(Code::ShortRegion, format!("Suspicious: {} is only {} nucleoptide length: {} .. {}", label(region), region.end - region.start + 1, region.start, region.end)).for_transcript(&id).for_feature(&region.id));
             }
             let interval = region.start..region.end + 1; // bio uses half-open intervals
             if let Some(overlap) = interval_tree.find(interval.clone()).next() {
-                errors.push(Error::fatal(format!(
+                errors.push(Error::fatal(Code::OverlappingRegions, format!(
                     "Transcript {} in chromosome {} has overlapping regions: {} and {} overlap with interval {:?}.",
                     id,
                     chromosome,
-                    region.id,
-                    overlap.data().id,
+                    label(region),
+                    label(overlap.data()),
                     overlap.interval()
-                )));
+                )).for_transcript(&id).for_feature(&region.id));
                 return None;
             }
             interval_tree.insert(interval, region);
@@ -55,6 +61,9 @@ impl Transcript {
             id,
             chromosome,
             regions,
+            gene_id: None,
+            gene_name: None,
+            cds: None,
         })
     }
 }
@@ -64,26 +73,32 @@ pub fn build_transcripts_from_regions(
     transcript_regions: Vec<TranscriptRegion>,
     errors: &mut Vec<Error>,
 ) -> Vec<Transcript> {
-    // Collect regions grouped by transcript ID
-    let mut transcript_map: HashMap<String, (String, Vec<Region>)> = HashMap::new();
+    // Collect regions grouped by transcript ID, keeping the order in which transcripts
+    // first appear so that every run produces the same output
+    let mut index: HashMap<Arc<str>, usize> = HashMap::new();
+    let mut grouped = Vec::new();
 
     for tr in transcript_regions {
-        let entry = transcript_map
-            .entry(tr.transcript_id.clone())
-            .or_insert_with(|| (tr.chromosome.clone(), Vec::new()));
+        let i = *index.entry(tr.transcript_id.clone()).or_insert_with(|| {
+            grouped.push((tr.transcript_id.clone(), tr.chromosome.clone(), tr.gene_id.clone(), Vec::new(), tr.line));
+            grouped.len() - 1
+        });
+        let entry = &mut grouped[i];
 
         // Sanity-check chromosome consistency
-        if entry.0 != tr.chromosome {
-            errors.push(Error::fatal(format!(
+        if entry.1 != tr.chromosome {
+            let mut error = Error::fatal(Code::MultipleChromosomes, format!(
                 "Transcript {} has regions from multiple chromosomes: {} vs {}",
                 tr.transcript_id,
-                entry.0,
+                entry.1,
                 tr.chromosome
-            )));
+            )).for_transcript(&*tr.transcript_id).for_feature(&tr.region_id);
+            locate(&mut error, &tr.gene_id, tr.line);
+            errors.push(error);
             continue;
         }
 
-        entry.1.push(Region {
+        entry.3.push(Region {
             id: tr.region_id.clone(),
             start: tr.start,
             end: tr.end,
@@ -94,14 +109,23 @@ pub fn build_transcripts_from_regions(
     // Now build validated transcripts
     let mut transcripts = Vec::new();
 
-    for (id, (chromosome, regions)) in transcript_map {
-        if let Some(transcript) = Transcript::new(id.clone(), chromosome, regions, errors) {
+    for (id, chromosome, gene_id, regions, line) in grouped {
+        let first = errors.len();
+        let transcript = Transcript::new(id.to_string(), chromosome.to_string(), regions, errors);
+        // Problems of a transcript are located at its first feature
+        for error in &mut errors[first..] {
+            locate(error, &gene_id, line);
+        }
+        if let Some(mut transcript) = transcript {
             if transcript.regions.len() < 2 {
-                errors.push(Error::warning(format!(
+                let mut error = Error::warning(Code::SingleRegion, format!(
                     "Transcript {} has only one feature; skipping", id
-                )));
+                )).for_transcript(&*id);
+                locate(&mut error, &gene_id, line);
+                errors.push(error);
                 continue;
             }
+            transcript.gene_id = gene_id.map(|g| g.to_string());
             transcripts.push(transcript);
         }
     }
@@ -109,42 +133,88 @@ pub fn build_transcripts_from_regions(
     transcripts
 }
 
-/// Load genome sequences into memory from FASTA file.
-fn load_genome_to_memory(fasta_path: &str) -> Result<HashMap<String, Vec<u8>>> {
+/// Attach the gene of a transcript and the line of its feature to a problem.
+fn locate(error: &mut Error, gene_id: &Option<Arc<str>>, line: usize) {
+    if let Some(gene_id) = gene_id {
+        error.gene_id = Some(gene_id.to_string());
+    }
+    error.line = Some(line);
+}
+
+/// Build transcripts from a parsed annotation, attaching coding spans from its CDS lines
+/// and gene names from the gene lines.
+pub fn build_transcripts(annotation: Annotation, errors: &mut Vec<Error>) -> Vec<Transcript> {
+    let first = errors.len();
+    let mut transcripts = build_transcripts_from_regions(annotation.regions, errors);
+    if !annotation.source.is_empty() {
+        for error in &mut errors[first..] {
+            error.file.get_or_insert_with(|| annotation.source.clone());
+        }
+    }
+    assign_coding_spans(&mut transcripts, &annotation.cds);
+    for transcript in transcripts.iter_mut() {
+        if let Some(gene_id) = &transcript.gene_id {
+            transcript.gene_name = annotation.gene_names.get(gene_id).cloned();
+        }
+    }
+    transcripts
+}
+
+/// Set the CDS span of each transcript from the CDS lines that name it as a parent.
+/// The span is clipped to the transcript extent; CDS on another chromosome or strand is ignored.
+pub fn assign_coding_spans(transcripts: &mut [Transcript], cds: &[TranscriptRegion]) {
+    let mut spans: HashMap<&str, Vec<&TranscriptRegion>> = HashMap::new();
+    for c in cds {
+        spans.entry(&c.transcript_id).or_default().push(c);
+    }
+
+    for transcript in transcripts.iter_mut() {
+        let Some(parts) = spans.get(transcript.id.as_str()) else {
+            continue;
+        };
+        let strand = transcript.strand();
+        let parts: Vec<_> = parts
+            .iter()
+            .filter(|c| *c.chromosome == *transcript.chromosome && c.strand == strand)
+            .collect();
+        let (Some(start), Some(end)) = (
+            parts.iter().map(|c| c.start).min(),
+            parts.iter().map(|c| c.end).max(),
+        ) else {
+            continue;
+        };
+        let start = start.max(transcript.start());
+        let end = end.min(transcript.end());
+        if start <= end {
+            transcript.cds = Some((start, end));
+        }
+    }
+}
+
+/// Load genome sequences into memory from FASTA file, naming them through `names`.
+pub fn load_genome_to_memory(fasta_path: &str, names: &mut SequenceNames) -> Result<HashMap<String, Vec<u8>>> {
     let reader = fasta::Reader::from_file(fasta_path)?;
     let mut genome = HashMap::new();
 
     for record in reader.records() {
         let record = record?;
-        genome.insert(record.id().to_owned(), record.seq().to_owned());
+        if let Some(name) = names.name(record.id()) {
+            genome.insert(name, record.seq().to_owned());
+        }
     }
     Ok(genome)
 }
 
 /// Extract sequence for a single transcript.
-fn extract_transcript_sequence(
-    genome: &HashMap<String, Vec<u8>>,
+pub(crate) fn extract_transcript_sequence(
+    genome: &dyn GenomeSource,
     transcript: &Transcript,
 ) -> Result<Vec<u8>> {
-    let chromosome_seq = genome.get(&transcript.chromosome).ok_or_else(|| {
-        anyhow::anyhow!(
-            "Chromosome '{}' not found in genome.",
-            transcript.chromosome
-        )
-    })?;
-
     let mut sequence = Vec::with_capacity(transcript.size());
 
-    // Sort regions according to strand orientation
-    let mut sorted_regions = transcript.regions.clone();
-    sorted_regions.sort_by_key(|r| r.start);
-
-    // Extract sequences:
-    for region in &sorted_regions {
-        let start = region.start - 1;
-        let end = region.end;
-
-        sequence.extend_from_slice(&chromosome_seq[start..end]);
+    // Extract sequences in ascending genomic order:
+    for region in transcript.ascending_regions() {
+        genome.fetch(&transcript.chromosome, region.start, region.end, &mut sequence)?;
     }
 
     // Reverse complement entire sequence for minus strand:
@@ -161,19 +231,32 @@ pub fn build_transcriptome_sequences(
     genome_fasta_path: &str,
     output_fasta_path: &str,
 ) -> Result<()> {
-    // Load genome into memory
-    let genome = load_genome_to_memory(genome_fasta_path)?;
+    // Load genome into memory, or open it through its index
+    let genome = open_genome(genome_fasta_path)?;
+    write_transcriptome(genome.as_ref(), transcripts, output_fasta_path, 1)
+}
 
+/// Extract transcript sequences from an already loaded genome and write them to FASTA.
+/// Extraction is spread over `threads` threads; the output is the same for any number.
+pub fn write_transcriptome(
+    genome: &dyn GenomeSource,
+    transcripts: &[Transcript],
+    output_fasta_path: &str,
+    threads: usize,
+) -> Result<()> {
     // Open FASTA writer for output
     let mut writer = fasta::Writer::to_file(output_fasta_path)?;
 
     // Extract and write each transcript
-    for transcript in transcripts {
-        let seq = extract_transcript_sequence(&genome, transcript)?;
-        writer.write(&transcript.id, None, &seq)?;
-    }
-
-    Ok(())
+    for_each_in_order(
+        transcripts,
+        threads,
+        |transcript| extract_transcript_sequence(genome, transcript),
+        |transcript, seq| {
+            writer.write(&transcript.id, None, &seq?)?;
+            Ok(())
+        },
+    )
 }
 
 #[cfg(test)]
@@ -215,6 +298,21 @@ mod tests {
         assert!(!errors.is_empty());
     }
 
+    #[test]
+    fn test_segments_of_discontinuous_feature_are_numbered() {
+        let segments = || vec![build_region("cds1", 10, 20, Strand::Minus), build_region("cds1", 40, 50, Strand::Minus), build_region("e9", 60, 70, Strand::Minus)];
+        let mut errors = Vec::new();
+        let t = Transcript::new("tx1".into(), "chr1".into(), segments(), &mut errors).unwrap();
+        let labels: Vec<String> = t.regions.iter().map(|r| t.region_label(r)).collect();
+        assert_eq!(labels, ["e9", "cds1_seg1", "cds1_seg2"]);
+
+        let mut regions = segments();
+        regions[1].start = 15;
+        assert!(Transcript::new("tx1".into(), "chr1".into(), regions, &mut errors).is_none());
+        assert!(errors[0].message.contains("cds1_seg2 and cds1_seg1 overlap"), "{}", errors[0].message);
+        assert_eq!(errors[0].feature_id.as_deref(), Some("cds1"));
+    }
+
     #[test]
     fn test_extract_transcript_sequence_plus() {
         let genome = HashMap::from([("chr1".to_string(), b"ACGTAACCGGTT".to_vec())]);
@@ -239,8 +337,8 @@ mod tests {
 
     #[test]
     fn test_build_transcripts_from_regions() {
-        let trs = vec![TranscriptRegion { chromosome: "chr1".into(), start: 1, end: 3, strand: Strand::Plus, transcript_id: "tx1".into(), region_id: "r1".into(), gene_id: None },
-                        TranscriptRegion { chromosome: "chr1".into(), start: 5, end: 6, strand: Strand::Plus, transcript_id: "tx1".into(), region_id: "r2".into(), gene_id: None }];
+        let trs = vec![TranscriptRegion { chromosome: "chr1".into(), start: 1, end: 3, strand: Strand::Plus, transcript_id: "tx1".into(), region_id: "r1".into(), gene_id: None, phase: None, line: 1 },
+                        TranscriptRegion { chromosome: "chr1".into(), start: 5, end: 6, strand: Strand::Plus, transcript_id: "tx1".into(), region_id: "r2".into(), gene_id: None, phase: None, line: 2 }];
         let mut errors = Vec::new();
         let ts = build_transcripts_from_regions(trs, &mut errors);
         assert_eq!(errors.len(), 2);
@@ -248,4 +346,18 @@ mod tests {
         assert_eq!(ts.len(), 1);
         assert_eq!(ts[0].regions.len(), 2);
     }
+
+    #[test]
+    fn test_build_transcripts_assigns_cds_span() {
+        let region = |id: &str, start, end| TranscriptRegion { chromosome: "chr1".into(), start, end, strand: Strand::Minus, transcript_id: "tx1".into(), region_id: id.into(), gene_id: None, phase: None, line: 1 };
+        let annotation = Annotation {
+            regions: vec![region("e1", 10, 20), region("e2", 30, 40)],
+            cds: vec![region("c1", 15, 20), region("c2", 30, 45)],
+            ..Default::default()
+        };
+        let mut errors = Vec::new();
+        let ts = build_transcripts(annotation, &mut errors);
+        assert!(errors.is_empty());
+        assert_eq!(ts[0].cds, Some((15, 40)));
+    }
 }
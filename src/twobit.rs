@@ -0,0 +1,240 @@
+use crate::duplicates::SequenceNames;
+use crate::genome::{missing_chromosome, out_of_range, ByteSource, GenomeSource};
+use crate::packed::overlapping;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+const SIGNATURE: u32 = 0x1A41_2743;
+const BASES: [u8; 4] = *b"TCAG";
+
+/// Check the `.2bit` signature, in either byte order, at the start of a file.
+pub fn is_twobit(path: &str) -> Result<bool> {
+    let mut signature = [0u8; 4];
+    let read = File::open(path)?.read(&mut signature)?;
+    Ok(read == 4 && (u32::from_le_bytes(signature) == SIGNATURE || u32::from_be_bytes(signature) == SIGNATURE))
+}
+
+/// Layout of one sequence of a `.2bit` file. Blocks are 0-based and half-open.
+#[derive(Debug, PartialEq)]
+struct TwoBitEntry {
+    length: usize,
+    dna_offset: u64,
+    n_blocks: Vec<(usize, usize)>,
+    mask_blocks: Vec<(usize, usize)>,
+}
+
+/// UCSC `.2bit` genome, read by random access. Sequence headers (N and mask blocks) are
+/// read when the file is opened; the bases of each fetch are read from the file.
+pub struct TwoBitGenome<R: ByteSource> {
+    index: HashMap<String, TwoBitEntry>,
+    reader: R,
+}
+
+/// Reads the integers of a `.2bit` file in its byte order.
+struct Cursor<'a, R: ByteSource> {
+    reader: &'a R,
+    offset: u64,
+    big_endian: bool,
+}
+
+impl<R: ByteSource> Cursor<'_, R> {
+    fn bytes(&mut self, n: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0; n];
+        self.reader.read_range(self.offset, &mut buf)?;
+        self.offset += n as u64;
+        Ok(buf)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b: [u8; 4] = self.bytes(4)?.try_into().unwrap();
+        Ok(if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let b: [u8; 8] = self.bytes(8)?.try_into().unwrap();
+        Ok(if self.big_endian { u64::from_be_bytes(b) } else { u64::from_le_bytes(b) })
+    }
+
+    fn blocks(&mut self) -> Result<Vec<(usize, usize)>> {
+        let count = self.u32()? as usize;
+        let raw = self.bytes(count * 8)?;
+        let value = |i: usize| {
+            let b: [u8; 4] = raw[i * 4..i * 4 + 4].try_into().unwrap();
+            (if self.big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) }) as usize
+        };
+        Ok((0..count).map(|i| (value(i), value(i) + value(count + i))).collect())
+    }
+}
+
+impl TwoBitGenome<File> {
+    pub fn open(path: &str, names: &mut SequenceNames) -> Result<Self> {
+        Self::new(File::open(path)?, names)
+    }
+}
+
+impl<R: ByteSource> TwoBitGenome<R> {
+    /// Read the sequence headers, naming the sequences through `names`.
+    pub fn new(reader: R, names: &mut SequenceNames) -> Result<Self> {
+        let mut cursor = Cursor { reader: &reader, offset: 0, big_endian: false };
+        let signature = cursor.u32()?;
+        if signature != SIGNATURE {
+            cursor.big_endian = true;
+            if signature.swap_bytes() != SIGNATURE {
+                anyhow::bail!("Not a .2bit file: bad signature {:#x}", signature);
+            }
+        }
+        let version = cursor.u32()?;
+        if version > 1 {
+            anyhow::bail!("Unsupported .2bit version {}", version);
+        }
+        let count = cursor.u32()?;
+        cursor.u32()?; // reserved
+
+        let mut offsets = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let name_size = cursor.bytes(1)?[0] as usize;
+            let name = String::from_utf8(cursor.bytes(name_size)?)?;
+            // Version 1 has 64-bit offsets, for files over 4 GB
+            let offset = if version == 1 { cursor.u64()? } else { cursor.u32()? as u64 };
+            offsets.push((name, offset));
+        }
+
+        let mut index = HashMap::with_capacity(offsets.len());
+        for (name, offset) in offsets {
+            let Some(name) = names.name(&name) else {
+                continue;
+            };
+            cursor.offset = offset;
+            let length = cursor.u32()? as usize;
+            let n_blocks = cursor.blocks()?;
+            let mask_blocks = cursor.blocks()?;
+            cursor.u32()?; // reserved
+            index.insert(name, TwoBitEntry { length, dna_offset: cursor.offset, n_blocks, mask_blocks });
+        }
+
+        Ok(Self { index, reader })
+    }
+}
+
+impl<R: ByteSource> GenomeSource for TwoBitGenome<R> {
+    fn fetch(&self, chromosome: &str, start: usize, end: usize, out: &mut Vec<u8>) -> Result<()> {
+        let entry = self.index.get(chromosome).ok_or_else(|| missing_chromosome(chromosome))?;
+        if start == 0 || start > end + 1 || end > entry.length {
+            return Err(out_of_range(chromosome, start, end));
+        }
+        let (start, end) = (start - 1, end);
+        if start == end {
+            return Ok(());
+        }
+
+        let mut packed = vec![0; (end - 1) / 4 - start / 4 + 1];
+        self.reader.read_range(entry.dna_offset + (start / 4) as u64, &mut packed)?;
+
+        let from = out.len();
+        out.extend((start..end).map(|i| {
+            // The first base of a byte is in its most significant bits
+            let byte = packed[i / 4 - start / 4];
+            BASES[((byte >> (6 - (i % 4) * 2)) & 3) as usize]
+        }));
+        let bases = &mut out[from..];
+        for &(s, e) in overlapping(&entry.n_blocks, start, end) {
+            bases[s.max(start) - start..e.min(end) - start].fill(b'N');
+        }
+        for &(s, e) in overlapping(&entry.mask_blocks, start, end) {
+            bases[s.max(start) - start..e.min(end) - start].make_ascii_lowercase();
+        }
+        Ok(())
+    }
+
+    fn length(&self, chromosome: &str) -> Option<usize> {
+        self.index.get(chromosome).map(|e| e.length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encode sequences of ACGTN (any case) as a version 0, little-endian `.2bit` file.
+    fn twobit(sequences: &[(&str, &[u8])]) -> Vec<u8> {
+        let blocks = |seq: &[u8], hit: &dyn Fn(u8) -> bool| {
+            let mut starts = Vec::new();
+            let mut sizes = Vec::new();
+            for (i, &b) in seq.iter().enumerate() {
+                if !hit(b) {
+                    continue;
+                }
+                if i > 0 && hit(seq[i - 1]) {
+                    *sizes.last_mut().unwrap() += 1;
+                } else {
+                    starts.push(i as u32);
+                    sizes.push(1u32);
+                }
+            }
+            let mut out = (starts.len() as u32).to_le_bytes().to_vec();
+            starts.iter().chain(&sizes).for_each(|v| out.extend_from_slice(&v.to_le_bytes()));
+            out
+        };
+
+        let mut header = Vec::new();
+        for v in [SIGNATURE, 0, sequences.len() as u32, 0] {
+            header.extend_from_slice(&v.to_le_bytes());
+        }
+        let index_size: usize = sequences.iter().map(|(name, _)| 1 + name.len() + 4).sum();
+        let mut records = Vec::new();
+        for (name, seq) in sequences {
+            header.push(name.len() as u8);
+            header.extend_from_slice(name.as_bytes());
+            header.extend_from_slice(&((16 + index_size + records.len()) as u32).to_le_bytes());
+            records.extend_from_slice(&(seq.len() as u32).to_le_bytes());
+            records.extend(blocks(seq, &|b| b.eq_ignore_ascii_case(&b'N')));
+            records.extend(blocks(seq, &|b| b.is_ascii_lowercase()));
+            records.extend_from_slice(&0u32.to_le_bytes());
+            for chunk in seq.chunks(4) {
+                let mut byte = 0u8;
+                for (j, b) in chunk.iter().enumerate() {
+                    let code = BASES.iter().position(|x| x.eq_ignore_ascii_case(b)).unwrap_or(0) as u8;
+                    byte |= code << (6 - j * 2);
+                }
+                records.push(byte);
+            }
+        }
+        header.extend(records);
+        header
+    }
+
+    #[test]
+    fn test_twobit_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("g.2bit");
+        let chr1: &[u8] = b"ACGTacgtNNNNnnACGTTGCAaaTTGGC";
+        std::fs::write(&path, twobit(&[("chr1", chr1), ("chr2", b"GGCCA"), ("chr1", b"TTTT")])).unwrap();
+        assert!(is_twobit(path.to_str().unwrap()).unwrap());
+
+        // The repeated chr1 is reported and skipped, or renamed
+        let mut names = SequenceNames::new("g.2bit", true);
+        let renamed = TwoBitGenome::open(path.to_str().unwrap(), &mut names).unwrap();
+        assert_eq!(renamed.length("chr1_dup2"), Some(4));
+        let mut names = SequenceNames::new("g.2bit", false);
+        let genome = TwoBitGenome::open(path.to_str().unwrap(), &mut names).unwrap();
+        let mut errors = Vec::new();
+        names.finish(&mut errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, crate::error::Code::DuplicateSequenceId);
+        assert_eq!(genome.length("chr1"), Some(chr1.len()));
+        for start in 1..=chr1.len() {
+            for end in start - 1..=chr1.len() {
+                let mut out = Vec::new();
+                genome.fetch("chr1", start, end, &mut out).unwrap();
+                assert_eq!(out, &chr1[start - 1..end], "{}..{}", start, end);
+            }
+        }
+        let mut out = Vec::new();
+        genome.fetch("chr2", 2, 5, &mut out).unwrap();
+        assert_eq!(out, b"GCCA");
+        assert!(genome.fetch("chr2", 2, 6, &mut out).is_err());
+        assert!(genome.fetch("chr3", 1, 1, &mut out).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+use crate::error::{Code, Error};
+use crate::structures::{Strand, Transcript};
+use crate::genome::GenomeSource;
+use crate::parallel::for_each_in_order;
+use anyhow::Result;
+use bio::alphabets::dna;
+use bio::io::fasta;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// A sequence change taken from a VCF record, in genomic coordinates (1-based).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub chromosome: String,
+    pub pos: usize,
+    pub id: String,
+    pub reference: String,
+    pub alternative: String,
+}
+
+impl Variant {
+    /// Last genomic position covered by the reference allele.
+    pub fn end(&self) -> usize {
+        self.pos + self.reference.len() - 1
+    }
+}
+
+/// Non-overlapping variants per chromosome, sorted by position.
+#[derive(Debug, Default)]
+pub struct VariantSet {
+    by_chromosome: HashMap<String, Vec<Variant>>,
+}
+
+impl VariantSet {
+    /// Variants overlapping the genomic interval `start..=end`.
+    pub fn overlapping(&self, chromosome: &str, start: usize, end: usize) -> &[Variant] {
+        let Some(variants) = self.by_chromosome.get(chromosome) else {
+            return &[];
+        };
+        // Variants do not overlap, so their ends are sorted as well
+        let lo = variants.partition_point(|v| v.end() < start);
+        let hi = variants.partition_point(|v| v.pos <= end);
+        &variants[lo..hi.max(lo)]
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_chromosome.values().map(|v| v.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Load the variants to apply from a VCF. Without a sample, the first ALT allele of every
+/// record is used. With a sample, its GT decides: the allele of the given haplotype (1 or 2),
+/// or the first non-reference allele if no haplotype is given. Symbolic alleles are skipped,
+/// and so are variants overlapping one that was already taken.
+pub fn load_variants(
+    vcf_path: &str,
+    sample: Option<&str>,
+    haplotype: Option<usize>,
+    errors: &mut Vec<Error>,
+) -> Result<VariantSet> {
+    let reader = BufReader::new(File::open(vcf_path)?);
+    let mut sample_column = None;
+    let mut by_chromosome: HashMap<String, Vec<Variant>> = HashMap::new();
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.starts_with("#CHROM") {
+            if let Some(sample) = sample {
+                let column = line.split('\t').position(|c| c == sample);
+                if column.is_none() {
+                    anyhow::bail!("Sample '{}' not found in {}", sample, vcf_path);
+                }
+                sample_column = column;
+            }
+            continue;
+        } else if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+
+        let cols: Vec<&str> = line.split('\t').collect();
+        if cols.len() < 5 {
+            errors.push(Error::warning(Code::InvalidRecord, format!("{}:{} is not a VCF record", vcf_path, line_no + 1)).at(vcf_path, line_no + 1));
+            continue;
+        }
+        let Ok(pos) = cols[1].parse::<usize>() else {
+            errors.push(Error::warning(Code::InvalidRecord, format!("{}:{} has an invalid position", vcf_path, line_no + 1)).at(vcf_path, line_no + 1));
+            continue;
+        };
+        let alts: Vec<&str> = cols[4].split(',').collect();
+
+        let allele = match sample_column {
+            None => 1,
+            Some(column) => {
+                let gt_index = cols.get(8).and_then(|f| f.split(':').position(|k| k == "GT"));
+                let gt = gt_index.and_then(|i| cols.get(column)?.split(':').nth(i)).unwrap_or(".");
+                let alleles: Vec<Option<usize>> = gt.split(['/', '|']).map(|a| a.parse().ok()).collect();
+                let chosen = match haplotype {
+                    Some(h) => alleles.get(h - 1).copied().flatten(),
+                    None => alleles.iter().flatten().find(|&&a| a > 0).copied(),
+                };
+                match chosen {
+                    Some(a) if a > 0 => a,
+                    _ => continue,
+                }
+            }
+        };
+
+        let Some(alternative) = alts.get(allele - 1) else {
+            errors.push(Error::warning(Code::MissingAllele, format!("{}:{} has no allele {}", vcf_path, line_no + 1, allele)).at(vcf_path, line_no + 1));
+            continue;
+        };
+        let is_sequence = |allele: &str| !allele.is_empty() && allele.bytes().all(|b| b.is_ascii_alphabetic());
+        if !is_sequence(cols[3]) || !is_sequence(alternative) {
+            continue;
+        }
+
+        by_chromosome.entry(cols[0].to_owned()).or_default().push(Variant {
+            chromosome: cols[0].to_owned(),
+            pos,
+            id: cols[2].to_owned(),
+            reference: cols[3].to_owned(),
+            alternative: alternative.to_string(),
+        });
+    }
+
+    for variants in by_chromosome.values_mut() {
+        variants.sort_by_key(|v| v.pos);
+        let mut kept: Vec<Variant> = Vec::with_capacity(variants.len());
+        for v in variants.drain(..) {
+            match kept.last() {
+                Some(last) if v.pos <= last.end() => errors.push(Error::warning(Code::OverlappingVariants, format!(
+                    "Variant {}:{} overlaps variant at {}; skipping",
+                    v.chromosome, v.pos, last.pos
+                ))),
+                _ => kept.push(v),
+            }
+        }
+        *variants = kept;
+    }
+
+    Ok(VariantSet { by_chromosome })
+}
+
+/// Extract the sequence of a transcript with the variants inside its regions applied.
+/// Variants crossing a region boundary, or touching the two intronic bases of a splice site,
+/// are reported and not applied. Returns the sequence and the variants that were applied.
+pub fn extract_personal_sequence<'a>(
+    genome: &dyn GenomeSource,
+    transcript: &Transcript,
+    variants: &'a VariantSet,
+    errors: &mut Vec<Error>,
+) -> Result<(Vec<u8>, Vec<&'a Variant>)> {
+    let chromosome = &transcript.chromosome;
+    let regions = transcript.ascending_regions();
+    let mut sequence = Vec::with_capacity(transcript.size());
+    let mut applied = Vec::new();
+
+    for (i, region) in regions.iter().enumerate() {
+        // Splice sites are the intronic dinucleotides next to internal exon boundaries
+        if i + 1 < regions.len() {
+            let next = regions[i + 1];
+            for (start, end) in [(region.end + 1, region.end + 2), (next.start.saturating_sub(2), next.start - 1)] {
+                for v in variants.overlapping(&transcript.chromosome, start, end) {
+                    errors.push(Error::warning(Code::SpliceSiteVariant, format!(
+                        "Variant {}:{} overlaps a splice site of transcript {}",
+                        v.chromosome, v.pos, transcript.id
+                    )).for_transcript(&transcript.id));
+                }
+            }
+        }
+
+        let mut cursor = region.start;
+        for v in variants.overlapping(&transcript.chromosome, region.start, region.end) {
+            if v.pos < region.start || v.end() > region.end {
+                errors.push(Error::warning(Code::BoundaryVariant, format!(
+                    "Variant {}:{} crosses the boundary of region {} of transcript {}; not applied",
+                    v.chromosome, v.pos, transcript.region_label(region), transcript.id
+                )).for_transcript(&transcript.id).for_feature(&region.id));
+                continue;
+            }
+            let mut genomic = Vec::with_capacity(v.reference.len());
+            genome.fetch(chromosome, v.pos, v.end(), &mut genomic)?;
+            if !genomic.eq_ignore_ascii_case(v.reference.as_bytes()) {
+                errors.push(Error::warning(Code::ReferenceMismatch, format!(
+                    "Variant {}:{} REF {} does not match the genome; not applied",
+                    v.chromosome, v.pos, v.reference
+                )).for_transcript(&transcript.id).for_feature(&region.id));
+                continue;
+            }
+            genome.fetch(chromosome, cursor, v.pos - 1, &mut sequence)?;
+            sequence.extend_from_slice(v.alternative.as_bytes());
+            cursor = v.end() + 1;
+            applied.push(v);
+        }
+        genome.fetch(chromosome, cursor, region.end, &mut sequence)?;
+    }
+
+    if transcript.strand() == Strand::Minus {
+        sequence = dna::revcomp(sequence);
+    }
+
+    Ok((sequence, applied))
+}
+
+/// Write personalized transcript sequences to FASTA. Headers of transcripts with applied
+/// variants carry their count; the variants themselves go to the optional TSV table.
+pub fn write_personal_transcriptome(
+    genome: &dyn GenomeSource,
+    transcripts: &[Transcript],
+    output_fasta_path: &str,
+    variants: &VariantSet,
+    table_path: Option<&str>,
+    errors: &mut Vec<Error>,
+    threads: usize,
+) -> Result<()> {
+    let mut writer = fasta::Writer::to_file(output_fasta_path)?;
+    let mut table = table_path.map(create_variant_table).transpose()?;
+
+    // Each thread collects its own problems; they are appended in transcript order
+    for_each_in_order(
+        transcripts,
+        threads,
+        |transcript| {
+            let mut problems = Vec::new();
+            let extracted = extract_personal_sequence(genome, transcript, variants, &mut problems);
+            (extracted, problems)
+        },
+        |transcript, (extracted, problems)| {
+            errors.extend(problems);
+            let (seq, applied) = extracted?;
+            let description = (!applied.is_empty()).then(|| format!("variants={}", applied.len()));
+            writer.write(&transcript.id, description.as_deref(), &seq)?;
+            if let Some(table) = table.as_mut() {
+                write_variant_rows(table, transcript, &applied)?;
+            }
+            Ok(())
+        },
+    )?;
+
+    if let Some(mut table) = table {
+        table.flush()?;
+    }
+    Ok(())
+}
+
+/// Create the TSV table of applied variants, with its header.
+pub(crate) fn create_variant_table(path: &str) -> Result<BufWriter<File>> {
+    let mut table = BufWriter::new(File::create(path)?);
+    writeln!(table, "transcript_id\tchromosome\tposition\tvariant_id\tref\talt")?;
+    Ok(table)
+}
+
+pub(crate) fn write_variant_rows(table: &mut impl Write, transcript: &Transcript, applied: &[&Variant]) -> Result<()> {
+    for v in applied {
+        writeln!(
+            table,
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            transcript.id, v.chromosome, v.pos, v.id, v.reference, v.alternative
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_vcf(dir: &tempfile::TempDir, records: &[&str]) -> String {
+        let path = dir.path().join("v.vcf");
+        let mut text = String::from("##fileformat=VCFv4.2\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\tS1\n");
+        for r in records {
+            text.push_str(r);
+            text.push('\n');
+        }
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_load_variants_by_haplotype() {
+        let dir = tempfile::tempdir().unwrap();
+        let vcf = write_vcf(&dir, &[
+            "chr1\t3\ta\tA\tG\t.\t.\t.\tGT\t0|1",
+            "chr1\t5\tb\tC\tT,G\t.\t.\t.\tGT\t2|0",
+            "chr1\t8\tc\tG\t<DEL>\t.\t.\t.\tGT\t1|1",
+        ]);
+        let mut errors = Vec::new();
+        let first = load_variants(&vcf, Some("S1"), Some(1), &mut errors).unwrap();
+        let ids: Vec<&str> = first.overlapping("chr1", 1, 10).iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["b"]);
+        assert_eq!(first.overlapping("chr1", 5, 5)[0].alternative, "G");
+        let second = load_variants(&vcf, Some("S1"), Some(2), &mut errors).unwrap();
+        assert_eq!(second.len(), 1);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_extract_personal_sequence_with_indels() {
+        let dir = tempfile::tempdir().unwrap();
+        // Exons 1..6 and 11..16; an insertion and a deletion inside, a SNV at a splice site
+        let vcf = write_vcf(&dir, &[
+            "chr1\t2\tins\tC\tCTT\t.\t.\t.\tGT\t1|1",
+            "chr1\t8\tsplice\tA\tG\t.\t.\t.\tGT\t1|1",
+            "chr1\t12\tdel\tCGG\tC\t.\t.\t.\tGT\t1|1",
+        ]);
+        let genome = HashMap::from([("chr1".to_string(), b"ACGTACAAAATCGGAT".to_vec())]);
+        let t = Transcript::test("tx1", "chr1", Strand::Minus, &[(11, 16), (1, 6)]).region_ids(&["e2", "e1"]);
+        let mut errors = Vec::new();
+        let variants = load_variants(&vcf, None, None, &mut errors).unwrap();
+        let (seq, applied) = extract_personal_sequence(&genome, &t, &variants, &mut errors).unwrap();
+        // Plus-strand edited sequence ACTTGTAC + TCAT, reverse-complemented
+        assert_eq!(seq, b"ATGAGTACAAGT");
+        assert_eq!(applied.len(), 2);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("splice site"));
+    }
+}